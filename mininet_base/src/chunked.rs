@@ -0,0 +1,243 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+
+/// Longest chunk size or trailer line that is accepted.
+const MAX_LINE_LENGTH: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkedError {
+    /// A malformed chunk size, chunk end or trailer.
+    Invalid,
+    /// A chunk is longer than `with_max_chunk_bytes` allows.
+    ChunkTooLarge,
+    /// The body is longer than `with_max_body_bytes` allows.
+    BodyTooLarge,
+    /// More trailers than `with_max_trailers` allows.
+    TooManyTrailers
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+    Done
+}
+
+/// Incremental decoder for `Transfer-Encoding: chunked` bodies, used by
+/// both the client and the server.
+///
+/// The input can be split at arbitrary points, the decoder keeps the
+/// partial size and trailer lines between the calls.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: State,
+    line: Vec<u8>,
+    trailers: Vec<(String, String)>,
+    received: usize,
+    max_trailers: usize,
+    max_chunk_bytes: usize,
+    max_body_bytes: usize
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            line: vec![],
+            trailers: vec![],
+            received: 0,
+            max_trailers: 60,
+            max_chunk_bytes: usize::MAX,
+            max_body_bytes: usize::MAX
+        }
+    }
+
+    pub fn with_max_trailers(mut self, max_trailers: usize) -> Self {
+        self.max_trailers = max_trailers;
+        self
+    }
+
+    pub fn with_max_chunk_bytes(mut self, max_chunk_bytes: usize) -> Self {
+        self.max_chunk_bytes = max_chunk_bytes;
+        self
+    }
+
+    /// Checked against the announced chunk sizes, before their data arrives.
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Decodes the start of `input`. Returns the number of consumed bytes and
+    /// the body data found in them, which is a slice of `input`. Call again
+    /// with the rest of the input until nothing is consumed.
+    pub fn decode<'a>(&mut self, input: &'a [u8]) -> Result<(usize, &'a [u8]), ChunkedError> {
+        match self.state {
+            State::Data(remaining) => {
+                let n = remaining.min(input.len());
+                self.state = if n == remaining { State::DataEnd } else { State::Data(remaining - n) };
+                Ok((n, &input[..n]))
+            },
+            State::Done => Ok((0, &[])),
+            _ => {
+                let consumed = match self.read_line(input)? {
+                    Some(consumed) => consumed,
+                    None => return Ok((input.len(), &[]))
+                };
+                self.process_line()?;
+                self.line.clear();
+                Ok((consumed, &[]))
+            }
+        }
+    }
+
+    /// Decodes `input` up to the end of the body, appending the body data to
+    /// `body`. Returns the number of consumed bytes.
+    pub fn decode_into(&mut self, input: &[u8], body: &mut Vec<u8>) -> Result<usize, ChunkedError> {
+        let mut pos = 0;
+        while pos < input.len() && !self.is_done() {
+            let (consumed, data) = self.decode(&input[pos..])?;
+            body.extend_from_slice(data);
+            pos += consumed;
+        }
+        Ok(pos)
+    }
+
+    /// The terminating chunk and the trailers were received.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn trailers(&self) -> &[(String, String)] {
+        &self.trailers
+    }
+
+    pub fn take_trailers(&mut self) -> Vec<(String, String)> {
+        core::mem::take(&mut self.trailers)
+    }
+
+    /// Collects the input up to and including the next LF. Returns the number
+    /// of consumed bytes if the line is complete.
+    fn read_line(&mut self, input: &[u8]) -> Result<Option<usize>, ChunkedError> {
+        let (consumed, complete) = match input.iter().position(|b| *b == b'\n') {
+            Some(p) => (p + 1, true),
+            None => (input.len(), false)
+        };
+
+        if self.line.len() + consumed > MAX_LINE_LENGTH {
+            return Err(ChunkedError::Invalid);
+        }
+        self.line.extend_from_slice(&input[..consumed]);
+
+        Ok(if complete { Some(consumed) } else { None })
+    }
+
+    fn process_line(&mut self) -> Result<(), ChunkedError> {
+        let line = core::str::from_utf8(&self.line).map_err(|_| ChunkedError::Invalid)?;
+        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+
+        self.state = match self.state {
+            State::Size => {
+                // chunk extensions are ignored
+                let size = line.split(';').next().unwrap_or("").trim();
+                // only hex digits, from_str_radix would take a sign
+                if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(ChunkedError::Invalid);
+                }
+                let size = usize::from_str_radix(size, 16).map_err(|_| ChunkedError::ChunkTooLarge)?;
+
+                if size > self.max_chunk_bytes {
+                    return Err(ChunkedError::ChunkTooLarge);
+                }
+                self.received = match self.received.checked_add(size) {
+                    Some(received) if received <= self.max_body_bytes => received,
+                    _ => return Err(ChunkedError::BodyTooLarge)
+                };
+                if size == 0 { State::Trailers } else { State::Data(size) }
+            },
+            State::DataEnd => {
+                if !line.is_empty() {
+                    return Err(ChunkedError::Invalid);
+                }
+                State::Size
+            },
+            State::Trailers => {
+                if line.is_empty() {
+                    State::Done
+                } else {
+                    let (name, value) = line.split_once(':').ok_or(ChunkedError::Invalid)?;
+                    if self.trailers.len() >= self.max_trailers {
+                        return Err(ChunkedError::TooManyTrailers);
+                    }
+                    self.trailers.push((name.trim().to_string(), value.trim().to_string()));
+                    State::Trailers
+                }
+            },
+            State::Data(_) | State::Done => self.state
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_chunked_body_with_trailers() {
+        let data = b"4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\nGET /next";
+
+        // feed the data in every possible split
+        for split in 1..data.len() {
+            let mut decoder = ChunkedDecoder::new();
+            let mut body = vec![];
+            let mut rest = vec![];
+            for part in data.chunks(split) {
+                let consumed = decoder.decode_into(part, &mut body).unwrap();
+                rest.extend_from_slice(&part[consumed..]);
+            }
+
+            assert!(decoder.is_done());
+            assert_eq!(b"Wikipedia in \r\n\r\nchunks.", body.as_slice());
+            assert_eq!(&[("Expires".to_string(), "never".to_string())], decoder.trailers());
+            assert_eq!(b"GET /next", rest.as_slice());
+        }
+    }
+
+    #[test]
+    fn decode_invalid_chunked_body() {
+        let invalid = [&b"xyz\r\n"[..], b"2\r\nabc\r\n", b"+5\r\n", b"-1\r\n", b"\r\n", b"0\r\nno colon\r\n\r\n", &[b'a'; 2000]];
+        for data in invalid {
+            let mut body = vec![];
+            assert_eq!(Err(ChunkedError::Invalid), ChunkedDecoder::new().decode_into(data, &mut body), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn decode_limits() {
+        let decode = |data: &[u8]| {
+            let mut decoder = ChunkedDecoder::new().with_max_chunk_bytes(4).with_max_body_bytes(6).with_max_trailers(1);
+            let mut body = vec![];
+            decoder.decode_into(data, &mut body).map(|_| body)
+        };
+
+        assert_eq!(Ok(b"abcdef".to_vec()), decode(b"4\r\nabcd\r\n2\r\nef\r\n0\r\nA: 1\r\n\r\n"));
+        assert_eq!(Err(ChunkedError::ChunkTooLarge), decode(b"5\r\n"));
+        assert_eq!(Err(ChunkedError::ChunkTooLarge), decode(b"ffffffffffffffffff\r\n"));
+        assert_eq!(Err(ChunkedError::BodyTooLarge), decode(b"4\r\nabcd\r\n4\r\n"));
+        assert_eq!(Err(ChunkedError::TooManyTrailers), decode(b"0\r\nA: 1\r\nB: 2\r\n\r\n"));
+
+        // the sizes add up past usize::MAX without a body limit
+        let mut body = vec![];
+        let result = ChunkedDecoder::new().decode_into(b"1\r\na\r\nffffffffffffffff\r\n", &mut body);
+        assert_eq!(Err(ChunkedError::BodyTooLarge), result);
+    }
+}
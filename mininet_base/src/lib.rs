@@ -3,6 +3,7 @@
 #[macro_use]
 extern crate alloc;
 
+pub mod chunked;
 pub mod url;
 pub mod req;
 pub mod resp;
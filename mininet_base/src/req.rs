@@ -14,14 +14,17 @@ pub struct HttpServerHeader {
     pub value: String
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpMethod {
     Get,
     Head,
     Options,
     Put,
     Post,
-    Delete
+    Delete,
+    Patch,
+    Trace,
+    Connect
 }
 
 impl HttpMethod {
@@ -32,10 +35,18 @@ impl HttpMethod {
             HttpMethod::Options => "OPTIONS",
             HttpMethod::Put => "PUT",
             HttpMethod::Post => "POST",
-            HttpMethod::Delete => "DELETE"
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Connect => "CONNECT"
         }
     }
 
+    /// Repeating the request has the same effect as sending it once.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(*self, HttpMethod::Get | HttpMethod::Head | HttpMethod::Options | HttpMethod::Trace | HttpMethod::Put | HttpMethod::Delete)
+    }
+
     pub fn from_http(&self, s: &str) -> Option<Self> {
         let method = match s {
             "GET" => HttpMethod::Get,
@@ -44,6 +55,9 @@ impl HttpMethod {
             "PUT" => HttpMethod::Put,
             "POST" => HttpMethod::Post,
             "DELETE" => HttpMethod::Delete,
+            "PATCH" => HttpMethod::Patch,
+            "TRACE" => HttpMethod::Trace,
+            "CONNECT" => HttpMethod::Connect,
             _ => return None
         };
         Some(method)
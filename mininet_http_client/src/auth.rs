@@ -0,0 +1,380 @@
+use core::fmt;
+
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::{headers::HeaderMap, pool::ConnectionKey};
+
+/// Credentials for a request.
+#[derive(Clone, PartialEq)]
+pub enum Auth {
+    Basic { username: String, password: String },
+    Bearer(String),
+    /// Sent after the server answers with a `401` and a `Digest` challenge.
+    Digest { username: String, password: String }
+}
+
+impl Auth {
+    pub fn basic(username: &str, password: &str) -> Self {
+        Auth::Basic { username: username.to_string(), password: password.to_string() }
+    }
+
+    pub fn bearer(token: &str) -> Self {
+        Auth::Bearer(token.to_string())
+    }
+
+    pub fn digest(username: &str, password: &str) -> Self {
+        Auth::Digest { username: username.to_string(), password: password.to_string() }
+    }
+}
+
+// keeps the secrets out of the logs
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Basic { username, .. } => write!(f, "Basic({})", username),
+            Auth::Bearer(_) => write!(f, "Bearer"),
+            Auth::Digest { username, .. } => write!(f, "Digest({})", username)
+        }
+    }
+}
+
+pub(crate) fn basic_authorization(username: &str, password: &str) -> String {
+    format!("Basic {}", base64_encode(format!("{}:{}", username, password).as_bytes()))
+}
+
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut r = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                r.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                r.push('=');
+            }
+        }
+    }
+    r
+}
+
+/// Decodes padded base64, `None` if it is malformed.
+pub(crate) fn base64_decode(data: &str) -> Option<Vec<u8>> {
+    let data = data.as_bytes();
+    if !data.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut r = Vec::with_capacity(data.len() / 4 * 3);
+    for (i, chunk) in data.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|b| **b == b'=').count();
+        // only the last group is padded
+        if padding > 2 || padding > 0 && (i + 1) * 4 < data.len() {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for b in &chunk[..4 - padding] {
+            let value = match b {
+                b'A'..=b'Z' => b - b'A',
+                b'a'..=b'z' => b - b'a' + 26,
+                b'0'..=b'9' => b - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                _ => return None
+            };
+            n = n << 6 | value as u32;
+        }
+        n <<= 6 * padding;
+        r.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(r)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum DigestAlgorithm {
+    Md5,
+    Sha256
+}
+
+impl DigestAlgorithm {
+    fn hash(&self, data: &str) -> String {
+        match self {
+            DigestAlgorithm::Md5 => hex(&Md5::digest(data.as_bytes())),
+            DigestAlgorithm::Sha256 => hex(&Sha256::digest(data.as_bytes()))
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A `Digest` challenge from a `WWW-Authenticate` header, RFC 7616.
+#[derive(Debug, Clone)]
+pub(crate) struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: DigestAlgorithm,
+    /// The `-sess` variant of the algorithm.
+    session: bool,
+    /// Only `auth` is supported, `false` for the RFC 2069 compatibility mode.
+    qop_auth: bool
+}
+
+impl DigestChallenge {
+    /// The strongest supported challenge of the response.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let mut best: Option<DigestChallenge> = None;
+
+        for value in headers.get_all("WWW-Authenticate") {
+            for (scheme, params) in parse_challenges(value) {
+                if !scheme.eq_ignore_ascii_case("Digest") {
+                    continue;
+                }
+                if let Some(c) = Self::from_params(&params) {
+                    if best.as_ref().map(|b| b.algorithm) != Some(DigestAlgorithm::Sha256) {
+                        best = Some(c);
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    fn from_params(params: &[(String, String)]) -> Option<Self> {
+        let param = |name: &str| params.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+
+        let (algorithm, session) = match param("algorithm").unwrap_or("MD5") {
+            a if a.eq_ignore_ascii_case("MD5") => (DigestAlgorithm::Md5, false),
+            a if a.eq_ignore_ascii_case("MD5-sess") => (DigestAlgorithm::Md5, true),
+            a if a.eq_ignore_ascii_case("SHA-256") => (DigestAlgorithm::Sha256, false),
+            a if a.eq_ignore_ascii_case("SHA-256-sess") => (DigestAlgorithm::Sha256, true),
+            _ => return None
+        };
+
+        let qop_auth = match param("qop") {
+            Some(qop) if qop.split(',').any(|q| q.trim().eq_ignore_ascii_case("auth")) => true,
+            // only auth-int is offered
+            Some(_) => return None,
+            None => false
+        };
+
+        Some(DigestChallenge {
+            realm: param("realm")?.to_string(),
+            nonce: param("nonce")?.to_string(),
+            opaque: param("opaque").map(|o| o.to_string()),
+            algorithm,
+            session,
+            qop_auth
+        })
+    }
+
+    /// The `Authorization` header value. `nc` counts the requests sent with
+    /// this nonce, starting at 1.
+    pub fn authorization(&self, username: &str, password: &str, method: &str, uri: &str, nc: u32, cnonce: &str) -> String {
+        let h = |data: &str| self.algorithm.hash(data);
+        let nc = format!("{:08x}", nc);
+
+        let mut ha1 = h(&format!("{}:{}:{}", username, self.realm, password));
+        if self.session {
+            ha1 = h(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = h(&format!("{}:{}", method, uri));
+
+        let response = match self.qop_auth {
+            true => h(&format!("{}:{}:{}:{}:auth:{}", ha1, self.nonce, nc, cnonce, ha2)),
+            false => h(&format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let algorithm = match (self.algorithm, self.session) {
+            (DigestAlgorithm::Md5, false) => "MD5",
+            (DigestAlgorithm::Md5, true) => "MD5-sess",
+            (DigestAlgorithm::Sha256, false) => "SHA-256",
+            (DigestAlgorithm::Sha256, true) => "SHA-256-sess"
+        };
+
+        let mut header = format!("Digest username={}, realm={}, uri={}, algorithm={}, nonce={}",
+            quote(username), quote(&self.realm), quote(uri), algorithm, quote(&self.nonce));
+        if self.qop_auth {
+            header.push_str(&format!(", nc={}, cnonce={}, qop=auth", nc, quote(cnonce)));
+        }
+        header.push_str(&format!(", response={}", quote(&response)));
+        if let Some(ref opaque) = self.opaque {
+            header.push_str(&format!(", opaque={}", quote(opaque)));
+        }
+        header
+    }
+}
+
+/// The latest `Digest` challenge of each origin, answered preemptively by
+/// the following requests.
+#[derive(Debug, Default)]
+pub(crate) struct DigestSessions(Vec<(ConnectionKey, DigestChallenge, u32)>);
+
+impl DigestSessions {
+    const MAX_ORIGINS: usize = 4;
+
+    pub fn insert(&mut self, key: ConnectionKey, challenge: DigestChallenge) {
+        self.0.retain(|(k, _, _)| *k != key);
+        if self.0.len() >= Self::MAX_ORIGINS {
+            self.0.remove(0);
+        }
+        self.0.push((key, challenge, 0));
+    }
+
+    /// The challenge for the origin, with the nonce count of the next request.
+    pub fn next(&mut self, key: &ConnectionKey) -> Option<(&DigestChallenge, u32)> {
+        let (_, challenge, nc) = self.0.iter_mut().find(|(k, _, _)| k == key)?;
+        *nc += 1;
+        Some((challenge, *nc))
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            r.push('\\');
+        }
+        r.push(c);
+    }
+    r.push('"');
+    r
+}
+
+/// Splits a `WWW-Authenticate` value into the challenges and their parameters.
+fn parse_challenges(value: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut challenges: Vec<(String, Vec<(String, String)>)> = Vec::new();
+
+    for item in split_unquoted(value) {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+
+        // a new challenge starts with the scheme, the first parameter can follow it
+        let param = match item.find([' ', '=']) {
+            Some(p) if item.as_bytes()[p] == b' ' => {
+                challenges.push((item[..p].to_string(), Vec::new()));
+                item[p..].trim()
+            },
+            Some(_) => item,
+            None => {
+                challenges.push((item.to_string(), Vec::new()));
+                continue;
+            }
+        };
+
+        if let (Some(challenge), Some((name, value))) = (challenges.last_mut(), param.split_once('=')) {
+            challenge.1.push((name.trim().to_string(), unquote(value.trim())));
+        }
+    }
+
+    challenges
+}
+
+/// Splits on the commas outside of quoted strings.
+fn split_unquoted(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn unquote(s: &str) -> String {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => {
+            let mut r = String::with_capacity(inner.len());
+            let mut escaped = false;
+            for c in inner.chars() {
+                if c == '\\' && !escaped {
+                    escaped = true;
+                    continue;
+                }
+                escaped = false;
+                r.push(c);
+            }
+            r
+        },
+        None => s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn basic_credentials() {
+        assert_eq!("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==", basic_authorization("Aladdin", "open sesame"));
+        assert_eq!("", base64_encode(b""));
+        assert_eq!("Zg==", base64_encode(b"f"));
+        assert_eq!("Zm8=", base64_encode(b"fo"));
+        assert_eq!("Zm9v", base64_encode(b"foo"));
+
+        for data in [&b""[..], b"f", b"fo", b"foo", b"\x00\xff\x10\n"] {
+            assert_eq!(Some(data.to_vec()), base64_decode(&base64_encode(data)));
+        }
+        for invalid in ["Zg=", "Z===", "Zg==Zm9v", "Zm9\n", "Zm-v"] {
+            assert_eq!(None, base64_decode(invalid), "{}", invalid);
+        }
+    }
+
+    fn challenge(value: &str) -> Option<DigestChallenge> {
+        DigestChallenge::from_headers(&HeaderMap::from(vec![("WWW-Authenticate".to_string(), value.to_string())]))
+    }
+
+    #[test]
+    fn digest_rfc7616_examples() {
+        let params = r#"realm="http-auth@example.org", qop="auth, auth-int", nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#;
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+        let md5 = challenge(&format!("Digest {}, algorithm=MD5", params)).unwrap();
+        let header = md5.authorization("Mufasa", "Circle of Life", "GET", "/dir/index.html", 1, cnonce);
+        assert!(header.contains(r#"response="8ca523f5e9506fed4657c9700eebdbec""#), "{}", header);
+        assert!(header.contains("nc=00000001") && header.contains("qop=auth"));
+        assert!(header.contains(r#"opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#));
+
+        // both challenges in one header, the stronger one is used
+        let both = format!("Digest {}, algorithm=MD5, Digest {}, algorithm=SHA-256", params, params);
+        let sha = challenge(&both).unwrap();
+        let header = sha.authorization("Mufasa", "Circle of Life", "GET", "/dir/index.html", 1, cnonce);
+        assert!(header.contains(r#"response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1""#), "{}", header);
+        assert!(header.contains("algorithm=SHA-256"));
+    }
+
+    #[test]
+    fn digest_challenge_parsing() {
+        let c = challenge(r#"Basic realm="x", Digest realm="a \"b\", c", nonce=abc"#).unwrap();
+        assert_eq!(("a \"b\", c", "abc", false), (c.realm.as_str(), c.nonce.as_str(), c.qop_auth));
+
+        assert!(challenge(r#"Digest realm="a", nonce="n", qop="auth-int""#).is_none());
+        assert!(challenge(r#"Digest realm="a", nonce="n", algorithm=SHA-512-256"#).is_none());
+        assert!(challenge(r#"Basic realm="a""#).is_none());
+    }
+}
@@ -0,0 +1,924 @@
+use core::{ops::Add, time::Duration};
+
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use mininet_base::{addr::{Ipv4Addr, SocketAddr, SocketAddrV4}, req::HttpMethod, stack::{SystemEnvironment, TcpError, TcpSocket, TcpStack, with_timeout}, url::{Authority, Url}};
+use alloc::boxed::Box;
+use async_trait::async_trait;
+use futures::FutureExt;
+use slog::{Logger, debug, info, o};
+
+use crate::{HttpClientError, auth::{Auth, DigestChallenge, DigestSessions, basic_authorization}, cookie::CookieJar, oauth::TokenManager, interceptor::{Chain, HttpInterceptor, HttpInterceptorRunner, Null}, multipart::Multipart, proxy::Proxy, decode::{ACCEPT_ENCODING, ContentDecoder}, pool::{ConnectionKey, ConnectionPool, PoolConfig}, request::{HttpRequest, RequestBuilder}, redirect::{Redirect, RedirectPolicy, is_redirect, redirect_request}, headers::HeaderMap, response::{Response, ResponseHead, ResponseLimits, is_success, read_head}, retry::{Jitter, RetryCause, RetryPolicy}, stream::{BodyReader, StreamingResponse}, timeout::{ReadTimer, Timeouts}};
+
+/// A reusable HTTP client that owns the network stack.
+pub struct HttpClient<S, E, I = Null>
+    where S: TcpStack, E: SystemEnvironment
+{
+    transport: Transport<S, E>,
+    base_url: Option<Url>,
+    default_headers: Vec<(String, String)>,
+    interceptors: I
+}
+
+/// Sends the requests, everything of the client apart from building the
+/// requests and the interceptors.
+pub(crate) struct Transport<S, E>
+    where S: TcpStack, E: SystemEnvironment
+{
+    logger: Logger,
+    stack: S,
+    env: E,
+    timeouts: Timeouts,
+    limits: ResponseLimits,
+    pool: ConnectionPool<S::TcpSocket, E>,
+    redirect_policy: RedirectPolicy,
+    retry_policy: RetryPolicy,
+    jitter: Jitter,
+    decompress: bool,
+    auth: Option<Auth>,
+    digest: DigestSessions,
+    proxy: Option<Proxy>,
+    cookies: Option<CookieJar>,
+    tokens: Option<TokenManager>,
+    /// Fires when the cached access token is to be refreshed.
+    token_expiry: Option<E::Timeout>
+}
+
+/// A response head received on a connection, with the body still to be read.
+pub(crate) struct Exchange<T, E> {
+    pub socket: T,
+    pub key: ConnectionKey,
+    pub head: ResponseHead,
+    pub body: BodyReader,
+    pub timer: ReadTimer<E>,
+    /// The connection can go back to the pool once the body is read.
+    pub reusable: bool,
+    pub redirects: Vec<Redirect>,
+    /// Set when the body is decoded by the client.
+    pub decoder: Option<ContentDecoder>
+}
+
+/// Failure of a request on a single connection.
+enum ExchangeError {
+    /// A reused connection was closed by the server before the request
+    /// reached it, the request can be sent again on a new connection.
+    Stale(HttpClientError),
+    Failed(HttpClientError)
+}
+
+impl<S, E> HttpClient<S, E>
+    where S: TcpStack, E: SystemEnvironment
+{
+    pub fn new(logger: &Logger, stack: S, env: E) -> Self {
+        HttpClient {
+            transport: Transport {
+                logger: logger.new(o!("ctx" => "http_client")),
+                stack,
+                env,
+                timeouts: Timeouts::default(),
+                limits: ResponseLimits::default(),
+                pool: ConnectionPool::new(PoolConfig::default()),
+                redirect_policy: RedirectPolicy::default(),
+                retry_policy: RetryPolicy::none(),
+                jitter: Jitter::new(0),
+                decompress: true,
+                auth: None,
+                digest: DigestSessions::default(),
+                proxy: None,
+                cookies: None,
+                tokens: None,
+                token_expiry: None
+            },
+            base_url: None,
+            default_headers: vec![],
+            interceptors: Null
+        }
+    }
+}
+
+impl<S, E, I> HttpClient<S, E, I>
+    where S: TcpStack, E: SystemEnvironment, I: HttpInterceptor
+{
+    /// Relative request URLs are appended to the path of this URL.
+    pub fn with_base_url(mut self, base_url: &str) -> Result<Self, HttpClientError> {
+        let url = Url::parse(base_url).map_err(|_| HttpClientError::UrlParseError)?.1;
+        self.base_url = Some(url);
+        Ok(self)
+    }
+
+    /// Sent with every request, unless the request sets a header with the same name.
+    pub fn with_default_header(mut self, name: &str, value: &str) -> Self {
+        self.default_headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Limits the time of the whole request, from resolving the address to the end of the body.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.transport.timeouts.total = Some(timeout);
+        self
+    }
+
+    /// Default limits, requests can override them individually.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.transport.timeouts = timeouts;
+        self
+    }
+
+    /// Bounds on the headers and on the bodies read into memory.
+    pub fn with_response_limits(mut self, limits: ResponseLimits) -> Self {
+        self.transport.limits = limits;
+        self
+    }
+
+    /// Keep-alive connections, use [`PoolConfig::disabled`] to close the connection after each request.
+    pub fn with_connection_pool(mut self, config: PoolConfig) -> Self {
+        self.transport.pool = ConnectionPool::new(config);
+        self
+    }
+
+    pub fn with_redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.transport.redirect_policy = policy;
+        self
+    }
+
+    /// Requests can override the policy individually.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.transport.retry_policy = policy;
+        self
+    }
+
+    /// Seeds the backoff jitter, a per-device value keeps a fleet from retrying in lockstep.
+    pub fn with_jitter_seed(mut self, seed: u32) -> Self {
+        self.transport.jitter = Jitter::new(seed);
+        self
+    }
+
+    /// Sends `Accept-Encoding: gzip, deflate` and decodes the compressed
+    /// bodies, enabled by default. Requests that set their own
+    /// `Accept-Encoding` get the body as it was sent.
+    pub fn with_decompression(mut self, enabled: bool) -> Self {
+        self.transport.decompress = enabled;
+        self
+    }
+
+    /// Credentials sent with every request that doesn't set its own, to any
+    /// host apart from the targets of a redirect to another origin. Without
+    /// them, the user name and the password of the URL are used as `Basic`.
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.transport.auth = Some(auth);
+        self
+    }
+
+    /// Sends an OAuth2 access token with every request that doesn't have
+    /// other credentials, like `with_auth` does.
+    pub fn with_token_manager(mut self, tokens: TokenManager) -> Self {
+        self.transport.tokens = Some(tokens);
+        self
+    }
+
+    /// Sends the requests through the proxy, apart from its `no_proxy` hosts.
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.transport.proxy = Some(proxy);
+        self
+    }
+
+    /// Stores the cookies of the responses and sends them back with the
+    /// following requests, unless a request sets its own `Cookie` header.
+    pub fn with_cookie_jar(mut self, jar: CookieJar) -> Self {
+        self.transport.cookies = Some(jar);
+        self
+    }
+
+    /// Appends an interceptor, it runs after the ones added before it.
+    pub fn with_interceptor<N>(self, interceptor: N) -> HttpClient<S, E, <I as Add<Chain<N, Null>>>::Output>
+        where N: HttpInterceptor, I: Add<Chain<N, Null>>, <I as Add<Chain<N, Null>>>::Output: HttpInterceptor
+    {
+        HttpClient {
+            transport: self.transport,
+            base_url: self.base_url,
+            default_headers: self.default_headers,
+            interceptors: self.interceptors + Chain::new(interceptor)
+        }
+    }
+
+    /// Replaces the interceptors with a complete chain.
+    pub fn with_interceptors<J>(self, interceptors: J) -> HttpClient<S, E, J>
+        where J: HttpInterceptor
+    {
+        HttpClient {
+            transport: self.transport,
+            base_url: self.base_url,
+            default_headers: self.default_headers,
+            interceptors
+        }
+    }
+
+    /// Closes all the idle connections.
+    pub fn close_idle_connections(&mut self) {
+        self.transport.pool.clear();
+    }
+
+    pub fn default_headers(&self) -> &[(String, String)] {
+        &self.default_headers
+    }
+
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.transport.cookies.as_ref()
+    }
+
+    pub fn cookie_jar_mut(&mut self) -> Option<&mut CookieJar> {
+        self.transport.cookies.as_mut()
+    }
+
+    pub fn token_manager(&self) -> Option<&TokenManager> {
+        self.transport.tokens.as_ref()
+    }
+
+    pub fn token_manager_mut(&mut self) -> Option<&mut TokenManager> {
+        self.transport.tokens.as_mut()
+    }
+
+    pub fn interceptors(&mut self) -> &mut I {
+        &mut self.interceptors
+    }
+
+    pub fn logger(&self) -> &Logger {
+        &self.transport.logger
+    }
+
+    pub fn env(&self) -> &E {
+        &self.transport.env
+    }
+
+    pub fn stack(&mut self) -> &mut S {
+        &mut self.transport.stack
+    }
+
+    pub fn into_stack(self) -> S {
+        self.transport.stack
+    }
+
+    pub fn request(&mut self, method: HttpMethod, url: &str) -> RequestBuilder<'_, S, E, I> {
+        RequestBuilder::new(self, method, url)
+    }
+
+    pub fn get(&mut self, url: &str) -> RequestBuilder<'_, S, E, I> {
+        self.request(HttpMethod::Get, url)
+    }
+
+    pub fn head(&mut self, url: &str) -> RequestBuilder<'_, S, E, I> {
+        self.request(HttpMethod::Head, url)
+    }
+
+    pub fn post(&mut self, url: &str) -> RequestBuilder<'_, S, E, I> {
+        self.request(HttpMethod::Post, url)
+    }
+
+    pub fn put(&mut self, url: &str) -> RequestBuilder<'_, S, E, I> {
+        self.request(HttpMethod::Put, url)
+    }
+
+    pub fn patch(&mut self, url: &str) -> RequestBuilder<'_, S, E, I> {
+        self.request(HttpMethod::Patch, url)
+    }
+
+    pub fn delete(&mut self, url: &str) -> RequestBuilder<'_, S, E, I> {
+        self.request(HttpMethod::Delete, url)
+    }
+
+    pub fn options(&mut self, url: &str) -> RequestBuilder<'_, S, E, I> {
+        self.request(HttpMethod::Options, url)
+    }
+
+    /// Absolute URLs are used as they are, anything else is appended to the base URL.
+    pub fn resolve_url(&self, url: &str) -> Result<Url, HttpClientError> {
+        if Url::is_absolute(url) {
+            return Url::parse(url).map(|u| u.1).map_err(|_| HttpClientError::UrlParseError);
+        }
+
+        let mut base = self.base_url.clone().ok_or(HttpClientError::UrlParseError)?;
+        let base_path = base.path.as_deref().unwrap_or("").trim_end_matches('/');
+        let path = if url.starts_with('/') {
+            format!("{}{}", base_path, url)
+        } else {
+            format!("{}/{}", base_path, url)
+        };
+        base.path = Some(path);
+
+        Ok(base)
+    }
+
+    /// Sends the request through the interceptors and reads the whole
+    /// response. Any status code is returned as a response, see
+    /// `Response::error_for_status`.
+    pub async fn execute(&mut self, mut request: HttpRequest) -> Result<Response, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        self.interceptors.prepare(&mut request)?;
+        self.interceptors.intercept(request, SendRequest(&mut self.transport)).await
+    }
+
+    /// Sends the request and returns as soon as the status and the headers
+    /// are received. The body is read through the returned response.
+    ///
+    /// The interceptors see the response without its body. The head they
+    /// return is the head of the streaming response, and a response of their
+    /// own is returned instead of the one of the server.
+    pub async fn execute_streaming(&mut self, mut request: HttpRequest) -> Result<StreamingResponse<'_, S, E>, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        self.interceptors.prepare(&mut request)?;
+        let mut opened = None;
+        let response = self.interceptors.intercept(request, OpenRequest { transport: &mut self.transport, opened: &mut opened }).await?;
+
+        Ok(match opened {
+            Some(mut exchange) if response.body.is_empty() => {
+                exchange.head = ResponseHead {
+                    code: response.code,
+                    reason: response.reason,
+                    version: response.version,
+                    headers: response.headers
+                };
+                exchange.redirects = response.redirects;
+                StreamingResponse::new(&mut self.transport, exchange)
+            },
+            // the connection of a replaced response is closed
+            _ => StreamingResponse::buffered(&mut self.transport, response)
+        })
+    }
+
+    /// Opens a connection to the host for a protocol the client doesn't
+    /// speak itself, like TLS. When the proxy applies to the host, the
+    /// connection is a tunnel opened with `CONNECT`.
+    pub async fn connect_tunnel(&mut self, host: &str, port: u16) -> Result<S::TcpSocket, HttpClientError> {
+        self.transport.connect_tunnel(host, port).await
+    }
+
+    /// Sends the request with a `multipart/form-data` body, the body of the
+    /// request itself is replaced. The interceptors see the request with the
+    /// headers of the form, but without its body.
+    pub async fn execute_multipart(&mut self, mut request: HttpRequest, mut form: Multipart<'_>) -> Result<Response, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        self.interceptors.prepare(&mut request)?;
+        self.transport.frame_multipart(&mut request, &mut form);
+        self.interceptors.intercept(request, SendMultipart { transport: &mut self.transport, form }).await
+    }
+}
+
+/// The end of the interceptor chain, sends the request over the network.
+struct SendRequest<'a, S, E>(&'a mut Transport<S, E>)
+    where S: TcpStack, E: SystemEnvironment;
+
+#[async_trait]
+impl<'a, S, E> HttpInterceptorRunner for SendRequest<'a, S, E>
+    where S: TcpStack + Send, E: SystemEnvironment + Send + Sync
+{
+    async fn run(self, request: HttpRequest) -> Result<Response, HttpClientError> {
+        self.0.execute(request).await
+    }
+}
+
+/// The end of the interceptor chain for a streaming request. Passes on the
+/// head of the response, its body is read by the `StreamingResponse`.
+struct OpenRequest<'a, S, E>
+    where S: TcpStack, E: SystemEnvironment
+{
+    transport: &'a mut Transport<S, E>,
+    opened: &'a mut Option<Exchange<S::TcpSocket, E>>
+}
+
+#[async_trait]
+impl<'a, S, E> HttpInterceptorRunner for OpenRequest<'a, S, E>
+    where S: TcpStack + Send, E: SystemEnvironment + Send + Sync
+{
+    async fn run(self, request: HttpRequest) -> Result<Response, HttpClientError> {
+        let exchange = self.transport.open_streaming(request).await?;
+        let response = Response {
+            code: exchange.head.code,
+            reason: exchange.head.reason.clone(),
+            version: exchange.head.version,
+            headers: exchange.head.headers.clone(),
+            body: vec![],
+            trailers: HeaderMap::new(),
+            redirects: exchange.redirects.clone()
+        };
+        *self.opened = Some(exchange);
+        Ok(response)
+    }
+}
+
+/// The end of the interceptor chain for a form, which can only be sent once.
+struct SendMultipart<'a, 'f, S, E>
+    where S: TcpStack, E: SystemEnvironment
+{
+    transport: &'a mut Transport<S, E>,
+    form: Multipart<'f>
+}
+
+#[async_trait]
+impl<'a, 'f, S, E> HttpInterceptorRunner for SendMultipart<'a, 'f, S, E>
+    where S: TcpStack + Send, E: SystemEnvironment + Send + Sync
+{
+    async fn run(self, request: HttpRequest) -> Result<Response, HttpClientError> {
+        let mut form = self.form;
+        self.transport.execute_attempt(request, Some(&mut form)).await
+    }
+}
+
+impl<S, E> Transport<S, E>
+    where S: TcpStack, E: SystemEnvironment
+{
+    pub fn limits(&self) -> &ResponseLimits {
+        &self.limits
+    }
+
+    pub(crate) fn read_timer(&self) -> ReadTimer<E> {
+        ReadTimer::new(self.env.clone(), &self.timeouts)
+    }
+
+    pub async fn execute(&mut self, request: HttpRequest) -> Result<Response, HttpClientError> {
+        let policy = request.retry.unwrap_or(self.retry_policy);
+        if policy.max_retries == 0 {
+            return self.execute_attempt(request, None).await;
+        }
+
+        let mut attempt = 0;
+        loop {
+            let result = self.execute_attempt(request.clone(), None).await;
+            attempt += 1;
+
+            let wait = match result {
+                Ok(ref r) => self.retry_wait(&policy, request.method, attempt, RetryCause::Status(r.code), Some(&r.headers)),
+                Err(ref e) => self.retry_wait(&policy, request.method, attempt, RetryCause::Error(e), None)
+            };
+            match wait {
+                Some(wait) => self.env.timeout(wait).await,
+                None => return result
+            }
+        }
+    }
+
+    /// Replaces the body of the request with the headers of the form. The
+    /// form is sent once, it can't be repeated.
+    pub fn frame_multipart(&mut self, request: &mut HttpRequest, form: &mut Multipart<'_>) {
+        let jitter = &mut self.jitter;
+        let boundary = form.boundary_or_else(|| format!("mininet-{:08x}{:08x}", jitter.next_u32(), jitter.next_u32())).to_string();
+        debug!(self.logger, "Multipart boundary: {}", boundary);
+
+        request.body.clear();
+        request.headers.retain(|(n, _)| !n.eq_ignore_ascii_case("Content-Length") && !n.eq_ignore_ascii_case("Transfer-Encoding"));
+        if let Some(content_type) = form.content_type() {
+            request.set_header("Content-Type", &content_type);
+        }
+        match form.content_length() {
+            Some(len) => request.set_header("Content-Length", &len.to_string()),
+            None => request.set_header("Transfer-Encoding", "chunked")
+        }
+    }
+
+    async fn execute_attempt(&mut self, request: HttpRequest, form: Option<&mut Multipart<'_>>) -> Result<Response, HttpClientError> {
+        match request.timeouts.or(&self.timeouts).total {
+            Some(timeout) => {
+                let env = self.env.clone();
+                with_timeout(&env, self.execute_request(request, form), timeout).await.map_err(|_| HttpClientError::Timeout)?
+            },
+            None => self.execute_request(request, form).await
+        }
+    }
+
+    async fn execute_request(&mut self, request: HttpRequest, form: Option<&mut Multipart<'_>>) -> Result<Response, HttpClientError> {
+        let mut exchange = self.open(request, form).await?;
+
+        let mut body = exchange.body.read_to_end(&mut exchange.socket, &mut exchange.timer, self.limits.max_body_bytes).await?;
+        info!(self.logger, "Received body len: {}", body.len());
+        if let Some(ref mut decoder) = exchange.decoder {
+            body = decoder.decode_all(&body, self.limits.max_body_bytes)?;
+            debug!(self.logger, "Decoded body len: {}", body.len());
+        }
+
+        let trailers = exchange.body.take_trailers().into();
+        if exchange.reusable {
+            self.checkin(exchange.key, exchange.socket);
+        }
+
+        Ok(Response {
+            code: exchange.head.code,
+            reason: exchange.head.reason,
+            version: exchange.head.version,
+            headers: exchange.head.headers,
+            body,
+            trailers,
+            redirects: exchange.redirects
+        })
+    }
+
+    /// Opens the response of a streaming request, with its body still to be read.
+    pub async fn open_streaming(&mut self, request: HttpRequest) -> Result<Exchange<S::TcpSocket, E>, HttpClientError> {
+        let policy = request.retry.unwrap_or(self.retry_policy);
+        if policy.max_retries == 0 {
+            return self.open_attempt(request).await;
+        }
+
+        let mut attempt = 0;
+        let exchange = loop {
+            let result = self.open_attempt(request.clone()).await;
+            attempt += 1;
+
+            // a response that is retried is dropped together with its connection
+            let wait = match result {
+                Ok(ref e) => self.retry_wait(&policy, request.method, attempt, RetryCause::Status(e.head.code), Some(&e.head.headers)),
+                Err(ref e) => self.retry_wait(&policy, request.method, attempt, RetryCause::Error(e), None)
+            };
+            match wait {
+                Some(wait) => self.env.timeout(wait).await,
+                None => break result?
+            }
+        };
+
+        Ok(exchange)
+    }
+
+    async fn open_attempt(&mut self, request: HttpRequest) -> Result<Exchange<S::TcpSocket, E>, HttpClientError> {
+        match request.timeouts.or(&self.timeouts).total {
+            Some(timeout) => {
+                let env = self.env.clone();
+                with_timeout(&env, self.open(request, None), timeout).await.map_err(|_| HttpClientError::Timeout)?
+            },
+            None => self.open(request, None).await
+        }
+    }
+
+    /// The wait before the next attempt, `None` if the result should be returned.
+    fn retry_wait(&mut self, policy: &RetryPolicy, method: HttpMethod, attempt: usize, cause: RetryCause, headers: Option<&HeaderMap>) -> Option<Duration> {
+        if !policy.allows(method, attempt) || !(policy.classifier)(&cause) {
+            return None;
+        }
+
+        let wait = match headers.and_then(|h| policy.retry_after(h)) {
+            Some(after) if after > policy.max_retry_after => return None,
+            Some(after) => after,
+            None => policy.backoff(attempt, &mut self.jitter)
+        };
+        info!(self.logger, "Retrying after {:?}: {:?}", wait, cause);
+        Some(wait)
+    }
+
+    /// Sends the request, following the redirects allowed by the policy. A
+    /// request with a form is sent only once.
+    async fn open(&mut self, mut request: HttpRequest, mut form: Option<&mut Multipart<'_>>) -> Result<Exchange<S::TcpSocket, E>, HttpClientError> {
+        let mut redirects = vec![];
+        let mut auth = request.auth.take()
+            .or_else(|| self.auth.clone())
+            .or_else(|| request.url.credentials().map(|(username, password)| Auth::Basic { username, password }));
+        // an explicit header wins
+        if request.header("Authorization").is_some() {
+            auth = None;
+        }
+        let mut bearer = auth.is_none() && request.header("Authorization").is_none() && self.tokens.is_some();
+        let mut challenged = false;
+        let mut refreshed = false;
+
+        let decode = self.decompress && request.header("Accept-Encoding").is_none();
+        if decode {
+            request.set_header("Accept-Encoding", ACCEPT_ENCODING);
+        }
+
+        loop {
+            let authorization = match bearer {
+                true => self.bearer_authorization().await?,
+                false => auth.as_ref().and_then(|a| self.authorization(a, &request))
+            };
+            let resendable = form.is_none();
+            let mut exchange = self.start(&request, authorization.as_deref(), form.as_deref_mut()).await?;
+            let code = exchange.head.code;
+
+            if let (401, true, false, true) = (code, bearer, refreshed, resendable) {
+                info!(self.logger, "The access token was rejected, fetching a new one.");
+                refreshed = true;
+                if let Some(ref mut tokens) = self.tokens {
+                    tokens.invalidate();
+                }
+                self.discard(exchange).await?;
+                continue;
+            }
+
+            if let (401, Some(Auth::Digest { .. }), false, true) = (code, &auth, challenged, resendable) {
+                if let Some(challenge) = DigestChallenge::from_headers(&exchange.head.headers) {
+                    debug!(self.logger, "Answering the Digest challenge of {:?}", exchange.key);
+                    challenged = true;
+                    self.digest.insert(ConnectionKey::new(&request.url)?, challenge);
+                    self.discard(exchange).await?;
+                    continue;
+                }
+            }
+
+            let location = exchange.head.headers.get("Location");
+            if let (true, Some(location), true) = (is_redirect(code), location, resendable) {
+                if redirects.len() >= self.redirect_policy.max_redirects {
+                    if self.redirect_policy.max_redirects > 0 {
+                        return Err(HttpClientError::TooManyRedirects);
+                    }
+                } else {
+                    let next = redirect_request(&request, code, location)?;
+                    info!(self.logger, "Redirect {} to {:?}", code, next.url);
+
+                    if ConnectionKey::new(&next.url).ok() != ConnectionKey::new(&request.url).ok() {
+                        auth = None;
+                        bearer = false;
+                    }
+                    challenged = false;
+                    self.discard(exchange).await?;
+
+                    redirects.push(Redirect {
+                        status: code,
+                        from: request.url,
+                        location: next.url.clone()
+                    });
+                    request = next;
+                    continue;
+                }
+            }
+
+            if decode && !exchange.body.is_done() {
+                exchange.decoder = ContentDecoder::for_headers(&exchange.head.headers, &self.limits);
+                if exchange.decoder.is_some() {
+                    // the headers describe the decoded body
+                    exchange.head.headers.remove("Content-Encoding");
+                    exchange.head.headers.remove("Content-Length");
+                }
+            }
+
+            exchange.redirects = redirects;
+            return Ok(exchange);
+        }
+    }
+
+    /// The `Authorization` value for the request, `None` for `Digest` before
+    /// the origin sent a challenge.
+    fn authorization(&mut self, auth: &Auth, request: &HttpRequest) -> Option<String> {
+        match auth {
+            Auth::Basic { username, password } => Some(basic_authorization(username, password)),
+            Auth::Bearer(token) => Some(format!("Bearer {}", token)),
+            Auth::Digest { username, password } => {
+                let key = ConnectionKey::new(&request.url).ok()?;
+                let cnonce = format!("{:08x}{:08x}", self.jitter.next_u32(), self.jitter.next_u32());
+                let (challenge, nc) = self.digest.next(&key)?;
+                Some(challenge.authorization(username, password, request.method.to_http(), request.url.path_or_root(), nc, &cnonce))
+            }
+        }
+    }
+
+    /// The `Authorization` with the cached access token, a new one is
+    /// fetched when there is none or it is about to expire.
+    async fn bearer_authorization(&mut self) -> Result<Option<String>, HttpClientError> {
+        let tokens = match self.tokens {
+            Some(ref mut t) => t,
+            None => return Ok(None)
+        };
+        if self.token_expiry.as_mut().is_some_and(|expiry| expiry.now_or_never().is_some()) {
+            self.token_expiry = None;
+            tokens.invalidate();
+        }
+        if let Some(token) = tokens.token() {
+            return Ok(Some(format!("Bearer {}", token.access_token)));
+        }
+
+        let mut request = HttpRequest::new(HttpMethod::Post, tokens.token_url.clone());
+        let (authorization, body) = tokens.token_request();
+        request.set_header("Content-Type", "application/x-www-form-urlencoded");
+        request.set_header("Accept", "application/json");
+        request.body = body.into_bytes();
+        info!(self.logger, "Fetching an access token from {}", request.url.host());
+
+        let mut exchange = self.start(&request, authorization.as_deref(), None).await?;
+        let body = exchange.body.read_to_end(&mut exchange.socket, &mut exchange.timer, self.limits.max_body_bytes).await?;
+        if exchange.reusable {
+            self.checkin(exchange.key, exchange.socket);
+        }
+        if !is_success(exchange.head.code) {
+            return Err(HttpClientError::TokenRequestFailed(exchange.head.code));
+        }
+
+        match self.tokens {
+            Some(ref mut tokens) => {
+                let token = tokens.store(&body)?;
+                self.token_expiry = token.refresh_in.map(|refresh_in| self.env.timeout(refresh_in));
+                Ok(Some(format!("Bearer {}", token.access_token)))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Drops a response that isn't returned, the connection can only be
+    /// reused once the body is read.
+    async fn discard(&mut self, mut exchange: Exchange<S::TcpSocket, E>) -> Result<(), HttpClientError> {
+        if exchange.reusable && exchange.body.is_delimited() {
+            exchange.body.skip_to_end(&mut exchange.socket, &mut exchange.timer).await?;
+            self.checkin(exchange.key, exchange.socket);
+        }
+        Ok(())
+    }
+
+    /// Sends the request on a pooled or a new connection and reads the response head.
+    async fn start(&mut self, request: &HttpRequest, authorization: Option<&str>, form: Option<&mut Multipart<'_>>) -> Result<Exchange<S::TcpSocket, E>, HttpClientError> {
+        let timeouts = request.timeouts.or(&self.timeouts);
+
+        let mut request = request.clone();
+        if let Some(authorization) = authorization {
+            request.set_header("Authorization", authorization);
+        }
+        if !self.pool.config().is_enabled() {
+            request.set_header("Connection", "close");
+        }
+        if let (Some(jar), None) = (&self.cookies, request.header("Cookie")) {
+            if let Some(cookies) = jar.cookie_header(&request.url) {
+                request.set_header("Cookie", &cookies);
+            }
+        }
+
+        // the connections to the proxy are shared by all the hosts behind it
+        let proxy = self.proxy_for(&request.url)?.cloned();
+        let (key, head, server) = match proxy {
+            Some(proxy) => {
+                if request.url.scheme != "http" {
+                    return Err(HttpClientError::UnsupportedUrlScheme(request.url.scheme.clone()));
+                }
+                if let Some(authorization) = proxy.authorization() {
+                    request.set_header("Proxy-Authorization", &authorization);
+                }
+                (ConnectionKey::new(&proxy.url)?, request.to_proxy_http_head(), proxy.url)
+            },
+            None => (ConnectionKey::new(&request.url)?, request.to_http_head(), request.url.clone())
+        };
+        info!(self.logger, "HTTP sending: {}", head);
+
+        let mut reply = None;
+        // a form can't be sent again after a stale connection
+        let pooled = match form {
+            Some(_) => None,
+            None => self.pool.checkout(&key)
+        };
+        if let Some(socket) = pooled {
+            debug!(self.logger, "Reusing a pooled connection to {:?}", key);
+
+            match self.exchange(socket, &request, &head, &timeouts, true, None).await {
+                Ok(r) => reply = Some(r),
+                Err(ExchangeError::Stale(e)) => {
+                    debug!(self.logger, "The pooled connection was closed ({:?}), retrying on a new one.", e);
+                },
+                Err(ExchangeError::Failed(e)) => return Err(e)
+            }
+        }
+
+        let (socket, response_head, received, timer) = match reply {
+            Some(r) => r,
+            None => {
+                self.pool.reserve();
+                let socket = match timeouts.connect {
+                    Some(timeout) => {
+                        let env = self.env.clone();
+                        with_timeout(&env, self.connect(&server), timeout).await.map_err(|_| HttpClientError::ConnectTimeout)??
+                    },
+                    None => self.connect(&server).await?
+                };
+
+                match self.exchange(socket, &request, &head, &timeouts, false, form).await {
+                    Ok(r) => r,
+                    Err(ExchangeError::Stale(e) | ExchangeError::Failed(e)) => return Err(e)
+                }
+            }
+        };
+
+        if let Some(ref mut jar) = self.cookies {
+            jar.store_response(&request.url, &response_head.headers);
+        }
+
+        let framing = response_head.framing(request.method)?;
+        debug!(self.logger, "Response body framing: {:?}", framing);
+        let body = BodyReader::new(framing, received, &self.limits);
+
+        Ok(Exchange {
+            reusable: response_head.keep_alive() && !close_requested(&request) && body.is_delimited(),
+            socket,
+            key,
+            head: response_head,
+            body,
+            timer,
+            redirects: vec![],
+            decoder: None
+        })
+    }
+
+    /// Sends the request and reads the response head on a single connection.
+    async fn exchange(&mut self, mut socket: S::TcpSocket, request: &HttpRequest, head: &str, timeouts: &Timeouts, reused: bool, form: Option<&mut Multipart<'_>>) -> Result<(S::TcpSocket, ResponseHead, Vec<u8>, ReadTimer<E>), ExchangeError> {
+        let stale = |e: HttpClientError| if reused { ExchangeError::Stale(e) } else { ExchangeError::Failed(e) };
+
+        send_all(&mut socket, head.as_bytes()).await.map_err(|e| stale(e.into()))?;
+        match form {
+            Some(form) => form.write_to(&mut socket, request.header("Transfer-Encoding").is_some()).await.map_err(ExchangeError::Failed)?,
+            None => send_all(&mut socket, &request.body).await.map_err(|e| stale(e.into()))?
+        }
+
+        let mut timer = ReadTimer::new(self.env.clone(), timeouts);
+        let mut received = vec![];
+        let response_head = match read_head(&self.logger, &mut socket, &mut timer, &mut received, &self.limits).await {
+            Ok(h) => h,
+            Err(e) if received.is_empty() && request.method.is_idempotent() => return Err(stale(e)),
+            Err(e) => return Err(ExchangeError::Failed(e))
+        };
+
+        Ok((socket, response_head, received, timer))
+    }
+
+    /// The proxy for connections to the URL's host, if any.
+    fn proxy_for(&self, url: &Url) -> Result<Option<&Proxy>, HttpClientError> {
+        let port = url.port_or_default().ok_or(HttpClientError::UrlPortParseError)?;
+        Ok(self.proxy.as_ref().filter(|p| p.applies_to(&url.host(), port)))
+    }
+
+    pub async fn connect_tunnel(&mut self, host: &str, port: u16) -> Result<S::TcpSocket, HttpClientError> {
+        let target = Url::parse(&format!("http://{}:{}/", host, port)).map_err(|_| HttpClientError::UrlParseError)?.1;
+        let proxy = self.proxy_for(&target)?.cloned();
+
+        let server = proxy.as_ref().map(|p| &p.url).unwrap_or(&target);
+        let mut socket = match self.timeouts.connect {
+            Some(timeout) => {
+                let env = self.env.clone();
+                with_timeout(&env, self.connect(server), timeout).await.map_err(|_| HttpClientError::ConnectTimeout)??
+            },
+            None => self.connect(server).await?
+        };
+        let proxy = match proxy {
+            Some(proxy) => proxy,
+            None => return Ok(socket)
+        };
+
+        let mut head = format!("CONNECT {}:{} HTTP/1.1\r\nHost: {}:{}\r\n", host, port, host, port);
+        if let Some(authorization) = proxy.authorization() {
+            head.push_str(&format!("Proxy-Authorization: {}\r\n", authorization));
+        }
+        head.push_str("\r\n");
+        info!(self.logger, "Opening a tunnel to {}:{} through {:?}", host, port, proxy.url);
+        send_all(&mut socket, head.as_bytes()).await?;
+
+        let mut timer = ReadTimer::new(self.env.clone(), &self.timeouts);
+        let mut received = vec![];
+        let reply = read_head(&self.logger, &mut socket, &mut timer, &mut received, &self.limits).await?;
+        // nothing is expected from the host before the client speaks
+        if !is_success(reply.code) || !received.is_empty() {
+            return Err(HttpClientError::ProxyConnectFailed(reply.code));
+        }
+
+        Ok(socket)
+    }
+
+    /// Returns a connection with a fully read response to the pool.
+    pub(crate) fn checkin(&mut self, key: ConnectionKey, socket: S::TcpSocket) {
+        self.pool.checkin(&self.env, key, socket);
+    }
+
+    async fn connect(&mut self, url: &Url) -> Result<S::TcpSocket, HttpClientError> {
+        if url.scheme != "http" {
+            return Err(HttpClientError::UnsupportedUrlScheme(url.scheme.clone()));
+        }
+        let port = url.port_or_default().ok_or(HttpClientError::UrlPortParseError)?;
+
+        let socket_addr = resolve(&mut self.stack, url, port).await?;
+        info!(self.logger, "Socket address: {:?}", socket_addr);
+
+        Ok(self.stack.create_socket_connected(socket_addr).await?)
+    }
+}
+
+fn close_requested(request: &HttpRequest) -> bool {
+    request.header("Connection").map(|c| c.eq_ignore_ascii_case("close")) == Some(true)
+}
+
+pub(crate) async fn resolve<S>(stack: &mut S, url: &Url, port: u16) -> Result<SocketAddr, TcpError>
+    where S: TcpStack
+{
+    match url.authority {
+        Authority::Hostname(ref h) => {
+            stack.get_socket_address(&format!("{}:{}", h, port)).await
+        },
+        Authority::Ip((a, b, c, d)) => {
+            let ip = Ipv4Addr::new(a, b, c, d);
+            Ok(SocketAddrV4::new(ip, port).into())
+        },
+    }
+}
+
+/// Sends the whole buffer, the stack is allowed to accept only a part of it per call.
+pub(crate) async fn send_all<T>(socket: &mut T, mut data: &[u8]) -> Result<(), TcpError>
+    where T: TcpSocket
+{
+    while !data.is_empty() {
+        let n = socket.send(data).await?;
+        if n == 0 {
+            return Err(TcpError::Closed);
+        }
+        data = &data[n..];
+    }
+
+    Ok(())
+}
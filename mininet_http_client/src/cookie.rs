@@ -0,0 +1,284 @@
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use mininet_base::url::{Authority, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{date::parse_http_date, headers::HeaderMap};
+
+/// A cookie stored from a `Set-Cookie` header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Sent only to the host that set it, there was no `Domain` attribute.
+    pub host_only: bool,
+    pub path: String,
+    /// Seconds since the Unix epoch, `None` for a session cookie.
+    pub expires: Option<u64>,
+    pub secure: bool,
+    pub http_only: bool
+}
+
+impl Cookie {
+    /// Parses a `Set-Cookie` value received from the URL. Invalid cookies and
+    /// cookies for another domain are ignored.
+    pub fn parse(set_cookie: &str, url: &Url, now: Option<u64>) -> Option<Cookie> {
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let host = url.host().to_ascii_lowercase();
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url.path_or_root()),
+            expires: None,
+            secure: false,
+            http_only: false
+        };
+        let mut max_age = None;
+
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                k if k.eq_ignore_ascii_case("Domain") && !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain_matches(&host, &domain) || ((is_ip(url) || !domain.contains('.')) && domain != host) {
+                        return None;
+                    }
+                    cookie.host_only = domain == host && is_ip(url);
+                    cookie.domain = domain;
+                },
+                k if k.eq_ignore_ascii_case("Path") && value.starts_with('/') => cookie.path = value.to_string(),
+                // without a clock, the cookie lasts for the session
+                k if k.eq_ignore_ascii_case("Expires") && now.is_some() => {
+                    // the older format separates the date with dashes
+                    if let Some(expires) = parse_http_date(&value.replace('-', " ")) {
+                        cookie.expires = Some(expires);
+                    }
+                },
+                k if k.eq_ignore_ascii_case("Max-Age") => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(seconds);
+                    }
+                },
+                k if k.eq_ignore_ascii_case("Secure") => cookie.secure = true,
+                k if k.eq_ignore_ascii_case("HttpOnly") => cookie.http_only = true,
+                _ => {}
+            }
+        }
+
+        // a zero or negative Max-Age removes the cookie right away
+        match (max_age, now) {
+            (Some(seconds), _) if seconds <= 0 => cookie.expires = Some(0),
+            (Some(seconds), Some(now)) => cookie.expires = Some(now.saturating_add(seconds as u64)),
+            // without a clock, the cookie lasts for the session
+            (Some(_), None) => cookie.expires = None,
+            (None, _) => {}
+        }
+
+        // only a secure origin can set a secure cookie
+        if cookie.secure && url.scheme != "https" {
+            return None;
+        }
+
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: Option<u64>) -> bool {
+        match (self.expires, now) {
+            (Some(0), _) => true,
+            (Some(expires), Some(now)) => expires <= now,
+            _ => false
+        }
+    }
+
+    /// Whether the cookie is sent with a request to the URL.
+    pub fn matches(&self, url: &Url) -> bool {
+        let host = url.host().to_ascii_lowercase();
+        let domain = match self.host_only {
+            true => host == self.domain,
+            false => domain_matches(&host, &self.domain)
+        };
+
+        domain && path_matches(url.path_or_root(), &self.path) && (!self.secure || url.scheme == "https")
+    }
+}
+
+/// Cookies kept between the requests of a client.
+///
+/// The jar can be serialized to persist it, the clock has to be set again
+/// after it is restored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+    #[serde(skip)]
+    now: Option<fn() -> u64>
+}
+
+impl CookieJar {
+    /// The most cookies kept, the oldest ones are dropped first.
+    pub const MAX_COOKIES: usize = 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current time in seconds since the Unix epoch. Without a clock,
+    /// `Expires` is ignored and cookies only expire when the server removes
+    /// them with `Max-Age=0`.
+    pub fn with_clock(mut self, now: fn() -> u64) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    pub fn cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Cookie> {
+        self.cookies.iter().find(|c| c.name == name)
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    /// Drops the cookies without an expiry, like a browser that was closed.
+    pub fn clear_session_cookies(&mut self) {
+        self.cookies.retain(|c| c.expires.is_some());
+    }
+
+    pub fn remove_expired(&mut self) {
+        let now = self.now.map(|now| now());
+        self.cookies.retain(|c| !c.is_expired(now));
+    }
+
+    /// Stores a single `Set-Cookie` value received from the URL.
+    pub fn insert(&mut self, url: &Url, set_cookie: &str) {
+        let now = self.now.map(|now| now());
+        let cookie = match Cookie::parse(set_cookie, url, now) {
+            Some(c) => c,
+            None => return
+        };
+
+        self.cookies.retain(|c| c.name != cookie.name || c.domain != cookie.domain || c.path != cookie.path);
+        if cookie.is_expired(now) {
+            return;
+        }
+        if self.cookies.len() >= Self::MAX_COOKIES {
+            self.cookies.remove(0);
+        }
+        self.cookies.push(cookie);
+    }
+
+    /// Stores the cookies of a response.
+    pub fn store_response(&mut self, url: &Url, headers: &HeaderMap) {
+        for set_cookie in headers.get_all("Set-Cookie") {
+            self.insert(url, set_cookie);
+        }
+    }
+
+    /// The `Cookie` header for a request to the URL, the cookies with the
+    /// longest paths first.
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let now = self.now.map(|now| now());
+        let mut cookies: Vec<&Cookie> = self.cookies.iter()
+            .filter(|c| c.matches(url) && !c.is_expired(now))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+
+        cookies.sort_by_key(|c| core::cmp::Reverse(c.path.len()));
+        let pairs: Vec<String> = cookies.iter().map(|c| format!("{}={}", c.name, c.value)).collect();
+        Some(pairs.join("; "))
+    }
+}
+
+fn is_ip(url: &Url) -> bool {
+    matches!(url.authority, Authority::Ip(_))
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|h| h.ends_with('.'))
+}
+
+/// The directory of the request path, RFC 6265 section 5.1.4.
+fn default_path(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or("/");
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(p) => path[..p].to_string()
+    }
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    let path = request_path.split('?').next().unwrap_or("/");
+    match path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap().1
+    }
+
+    #[test]
+    fn cookie_attributes() {
+        let u = url("http://ui.example.com/admin/login?next=1");
+
+        let c = Cookie::parse("sid=abc123; Path=/; HttpOnly; Max-Age=3600", &u, Some(1000)).unwrap();
+        assert_eq!(("sid", "abc123", "/", Some(4600), true, true), (c.name.as_str(), c.value.as_str(), c.path.as_str(), c.expires, c.http_only, c.host_only));
+
+        let c = Cookie::parse("lang=en; Domain=.Example.com; Expires=Wed, 21-Oct-2015 07:28:00 GMT", &u, Some(1000)).unwrap();
+        assert_eq!(("example.com", false, "/admin", Some(1445412480)), (c.domain.as_str(), c.host_only, c.path.as_str(), c.expires));
+        assert_eq!(None, Cookie::parse("lang=en; Expires=Wed, 21-Oct-2015 07:28:00 GMT", &u, None).unwrap().expires);
+        assert_eq!(None, Cookie::parse("lang=en; Expires=Wed, 21 Oct 12345678901234567890 07:28:00 GMT", &u, Some(1000)).unwrap().expires);
+        assert!(c.matches(&url("http://example.com/admin/x")));
+        assert!(c.matches(&url("http://other.example.com/admin")));
+        assert!(!c.matches(&url("http://example.com/administrator")));
+        assert!(!c.matches(&url("http://badexample.com/admin")));
+
+        assert!(Cookie::parse("a=b; Domain=other.com", &u, None).is_none());
+        assert!(Cookie::parse("a=b; Domain=com", &url("http://example.com/"), None).is_none());
+        assert!(Cookie::parse("a=b; Secure", &u, None).is_none());
+        assert!(Cookie::parse("=b", &u, None).is_none());
+        assert!(Cookie::parse("a=b; Secure", &url("https://ui.example.com/"), None).unwrap().secure);
+    }
+
+    #[test]
+    fn cookie_jar() {
+        let mut jar = CookieJar::new().with_clock(|| 10_000);
+        let u = url("http://192.168.1.1/cgi-bin/login");
+
+        jar.insert(&u, "session=s1; Path=/; Max-Age=600");
+        jar.insert(&u, "csrf=t1");
+        jar.insert(&u, "old=x; Expires=Thu, 01 Jan 1970 00:00:01 GMT");
+        assert_eq!(Some("csrf=t1; session=s1".to_string()), jar.cookie_header(&url("http://192.168.1.1/cgi-bin/status")));
+        assert_eq!(Some("session=s1".to_string()), jar.cookie_header(&url("http://192.168.1.1/")));
+        assert_eq!(None, jar.cookie_header(&url("http://192.168.1.2/")));
+
+        jar.insert(&u, "session=s2; Path=/; Max-Age=600");
+        assert_eq!("s2", jar.get("session").unwrap().value);
+        jar.insert(&u, "session=; Path=/; Max-Age=0");
+        assert!(jar.get("session").is_none());
+
+        let json = serde_json::to_string(&jar).unwrap();
+        let restored: CookieJar = serde_json::from_str(&json).unwrap();
+        assert_eq!(jar.cookies(), restored.cookies());
+    }
+}
@@ -0,0 +1,56 @@
+//! HTTP dates, `Sun, 06 Nov 1994 08:49:37 GMT`.
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Parses an IMF-fixdate into seconds since the Unix epoch. The obsolete
+/// RFC 850 and asctime formats are not supported.
+pub(crate) fn parse_http_date(s: &str) -> Option<u64> {
+    let mut parts = s.trim().split(' ').filter(|p| !p.is_empty());
+
+    let _weekday = parts.next()?.strip_suffix(',')?;
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+
+    let mut time = parts.next()?.split(':').map(|p| p.parse::<u64>().ok());
+    let (h, m, sec) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" || parts.next().is_some() || time.next().is_some() {
+        return None;
+    }
+
+    // later years would overflow the seconds
+    if !(1970..=9999).contains(&year) || !(1..=31).contains(&day) || h > 23 || m > 59 || sec > 60 {
+        return None;
+    }
+
+    Some(days_from_civil(year, month, day) * 86400 + h * 3600 + m * 60 + sec)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_dates() {
+        assert_eq!(Some(784111777), parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert_eq!(Some(0), parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"));
+        assert_eq!(Some(951782400), parse_http_date("Tue, 29 Feb 2000 00:00:00 GMT"));
+        assert_eq!(None, parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"));
+        assert_eq!(None, parse_http_date("Sun, 06 Nov 1994 08:49:37 CET"));
+        assert_eq!(None, parse_http_date("120"));
+        assert_eq!(Some(253402300799), parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT"));
+        assert_eq!(None, parse_http_date("Sun, 06 Nov 12345678901234567890 08:49:37 GMT"));
+    }
+}
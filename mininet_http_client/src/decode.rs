@@ -0,0 +1,315 @@
+use alloc::{boxed::Box, vec::Vec};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus, inflate::stream::{InflateState, inflate}};
+
+use crate::{HttpClientError, headers::HeaderMap, response::ResponseLimits};
+
+/// Sent when the client decodes the responses.
+pub(crate) const ACCEPT_ENCODING: &str = "gzip, deflate";
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const GZIP_TRAILER_LEN: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stage {
+    /// Collecting the gzip header, or the first byte of a deflate stream.
+    Header,
+    Data,
+    /// Collecting the CRC and the size after a gzip stream.
+    Trailer,
+    Done
+}
+
+/// Incremental decoder of `gzip` and `deflate` response bodies.
+///
+/// The inflater keeps a 32 KiB window, allocated with the decoder. The gzip
+/// header, with its optional file name and comment, is limited to the
+/// `max_header_bytes` of the response.
+pub struct ContentDecoder {
+    gzip: bool,
+    stage: Stage,
+    pending: Vec<u8>,
+    max_header_bytes: usize,
+    inflate: Option<Box<InflateState>>,
+    crc: u32,
+    size: u32
+}
+
+impl ContentDecoder {
+    /// A decoder for the `Content-Encoding` of the response, `None` for
+    /// the identity and for encodings that aren't supported.
+    pub fn for_headers(headers: &HeaderMap, limits: &ResponseLimits) -> Option<Self> {
+        match headers.get("Content-Encoding")?.trim() {
+            e if e.eq_ignore_ascii_case("gzip") || e.eq_ignore_ascii_case("x-gzip") => Some(Self::new(true, limits)),
+            e if e.eq_ignore_ascii_case("deflate") => Some(Self::new(false, limits)),
+            _ => None
+        }
+    }
+
+    fn new(gzip: bool, limits: &ResponseLimits) -> Self {
+        ContentDecoder {
+            gzip,
+            stage: Stage::Header,
+            pending: Vec::new(),
+            max_header_bytes: limits.max_header_bytes,
+            inflate: None,
+            crc: !0,
+            size: 0
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Decodes as much of the input as fits into `out`. Returns the number of
+    /// input bytes consumed and of bytes written. Data after the end of the
+    /// compressed stream is ignored.
+    pub fn decode(&mut self, input: &[u8], out: &mut [u8]) -> Result<(usize, usize), HttpClientError> {
+        let mut consumed = 0;
+
+        loop {
+            match self.stage {
+                Stage::Header if self.gzip => {
+                    let before = self.pending.len();
+                    self.pending.extend_from_slice(&input[consumed..]);
+                    match gzip_header_len(&self.pending)? {
+                        Some(len) => {
+                            consumed += len - before;
+                            self.pending.clear();
+                            self.inflate = Some(InflateState::new_boxed(DataFormat::Raw));
+                            self.stage = Stage::Data;
+                        },
+                        None if self.pending.len() > self.max_header_bytes => return Err(HttpClientError::InvalidContentEncoding),
+                        None => return Ok((input.len(), 0))
+                    }
+                },
+                Stage::Header => {
+                    let first = match input.get(consumed) {
+                        Some(b) => *b,
+                        None => return Ok((consumed, 0))
+                    };
+                    // "deflate" is meant to be zlib wrapped, but some servers send a raw stream
+                    let zlib = first & 0x0F == 8 && first >> 4 <= 7;
+                    let format = if zlib { DataFormat::Zlib } else { DataFormat::Raw };
+                    self.inflate = Some(InflateState::new_boxed(format));
+                    self.stage = Stage::Data;
+                },
+                Stage::Data => {
+                    let state = self.inflate.as_mut().ok_or(HttpClientError::InvalidContentEncoding)?;
+                    let r = inflate(state, &input[consumed..], out, MZFlush::None);
+                    consumed += r.bytes_consumed;
+
+                    let written = &out[..r.bytes_written];
+                    self.crc = crc32_update(self.crc, written);
+                    self.size = self.size.wrapping_add(written.len() as u32);
+
+                    match r.status {
+                        Ok(MZStatus::StreamEnd) => {
+                            self.inflate = None;
+                            self.stage = if self.gzip { Stage::Trailer } else { Stage::Done };
+                            if !written.is_empty() {
+                                return Ok((consumed, written.len()));
+                            }
+                        },
+                        // more input or more room for the output is needed
+                        Ok(_) | Err(MZError::Buf) => return Ok((consumed, written.len())),
+                        Err(_) => return Err(HttpClientError::InvalidContentEncoding)
+                    }
+                },
+                Stage::Trailer => {
+                    let n = (GZIP_TRAILER_LEN - self.pending.len()).min(input.len() - consumed);
+                    self.pending.extend_from_slice(&input[consumed..consumed + n]);
+                    consumed += n;
+                    if self.pending.len() < GZIP_TRAILER_LEN {
+                        return Ok((consumed, 0));
+                    }
+
+                    let crc = u32::from_le_bytes([self.pending[0], self.pending[1], self.pending[2], self.pending[3]]);
+                    let size = u32::from_le_bytes([self.pending[4], self.pending[5], self.pending[6], self.pending[7]]);
+                    if crc != !self.crc || size != self.size {
+                        return Err(HttpClientError::InvalidContentEncoding);
+                    }
+                    self.pending.clear();
+                    self.stage = Stage::Done;
+                },
+                Stage::Done => return Ok((input.len(), 0))
+            }
+        }
+    }
+
+    /// Decodes a complete body of up to `max_len` bytes once decoded.
+    pub fn decode_all(&mut self, mut input: &[u8], max_len: usize) -> Result<Vec<u8>, HttpClientError> {
+        let mut decoded = Vec::new();
+        let mut buf = [0; 512];
+
+        while !self.is_done() {
+            let (consumed, written) = self.decode(input, &mut buf)?;
+            input = &input[consumed..];
+            if decoded.len() + written > max_len {
+                return Err(HttpClientError::BodyTooLarge);
+            }
+            decoded.extend_from_slice(&buf[..written]);
+
+            if consumed == 0 && written == 0 {
+                return Err(HttpClientError::InvalidContentEncoding);
+            }
+        }
+
+        Ok(decoded)
+    }
+}
+
+/// Length of a complete gzip header, `None` if more data is needed.
+fn gzip_header_len(data: &[u8]) -> Result<Option<usize>, HttpClientError> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    if data.len() < 10 {
+        return Ok(None);
+    }
+    // the only compression method is deflate
+    if data[..2] != GZIP_MAGIC || data[2] != 8 {
+        return Err(HttpClientError::InvalidContentEncoding);
+    }
+
+    let flags = data[3];
+    let mut len = 10;
+
+    if flags & FEXTRA != 0 {
+        match data.get(len..len + 2) {
+            Some(x) => len += 2 + u16::from_le_bytes([x[0], x[1]]) as usize,
+            None => return Ok(None)
+        }
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            match data.get(len..).and_then(|d| d.iter().position(|b| *b == 0)) {
+                Some(end) => len += end + 1,
+                None => return Ok(None)
+            }
+        }
+    }
+    if flags & FHCRC != 0 {
+        len += 2;
+    }
+
+    Ok(if data.len() >= len { Some(len) } else { None })
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for b in data {
+        crc = CRC32_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec};
+    use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
+
+    use super::*;
+
+    fn payload() -> Vec<u8> {
+        (0..5000).flat_map(|i: u32| alloc::format!("{{\"id\":{},\"value\":\"sensor\"}},", i % 97).into_bytes()).collect()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        // FNAME set, to exercise the optional header fields
+        let mut gz = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 0xFF];
+        gz.extend_from_slice(b"data.json\0");
+        gz.extend_from_slice(&compress_to_vec(data, 6));
+        gz.extend_from_slice(&(!crc32_update(!0, data)).to_le_bytes());
+        gz.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        gz
+    }
+
+    fn decoder(encoding: &str) -> ContentDecoder {
+        let headers = HeaderMap::from(vec![("Content-Encoding".to_string(), encoding.to_string())]);
+        ContentDecoder::for_headers(&headers, &ResponseLimits::default()).unwrap()
+    }
+
+    /// Feeds the input in small parts into a small output buffer.
+    fn decode_in_parts(mut decoder: ContentDecoder, encoded: &[u8], part: usize) -> Result<Vec<u8>, HttpClientError> {
+        let mut decoded = vec![];
+        let mut out = [0; 7];
+        let mut pos = 0;
+        let mut end = 0;
+
+        while !decoder.is_done() {
+            end = (end + part).min(encoded.len());
+            loop {
+                let (consumed, written) = decoder.decode(&encoded[pos..end], &mut out)?;
+                pos += consumed;
+                decoded.extend_from_slice(&out[..written]);
+                if written == 0 && (consumed == 0 || pos == end) {
+                    break;
+                }
+            }
+            if end == encoded.len() && !decoder.is_done() {
+                return Err(HttpClientError::InvalidContentEncoding);
+            }
+        }
+
+        Ok(decoded)
+    }
+
+    #[test]
+    fn decode_gzip() {
+        let data = payload();
+        let encoded = gzip(&data);
+        assert!(encoded.len() < data.len() / 10);
+
+        assert_eq!(data, decoder("gzip").decode_all(&encoded, usize::MAX).unwrap());
+        // a small body can expand to far more than it was
+        assert!(matches!(decoder("gzip").decode_all(&encoded, data.len() - 1), Err(HttpClientError::BodyTooLarge)));
+        for part in [1, 5, 13, 512] {
+            assert_eq!(data, decode_in_parts(decoder("x-gzip"), &encoded, part).unwrap());
+        }
+
+        let mut corrupted = encoded.clone();
+        let crc_pos = corrupted.len() - 8;
+        corrupted[crc_pos] ^= 1;
+        assert!(decoder("gzip").decode_all(&corrupted, usize::MAX).is_err());
+        assert!(decoder("gzip").decode_all(&encoded[..encoded.len() - 3], usize::MAX).is_err());
+        assert!(decoder("gzip").decode_all(b"not gzip at all", usize::MAX).is_err());
+
+        // a file name that never ends isn't collected past the limit
+        let mut endless = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 0xFF];
+        endless.resize(10_000, b'a');
+        assert!(matches!(decode_in_parts(decoder("gzip"), &endless, 512), Err(HttpClientError::InvalidContentEncoding)));
+    }
+
+    #[test]
+    fn decode_deflate() {
+        let data = payload();
+
+        for encoded in [compress_to_vec_zlib(&data, 6), compress_to_vec(&data, 6)] {
+            assert_eq!(data, decoder("deflate").decode_all(&encoded, usize::MAX).unwrap());
+            assert_eq!(data, decode_in_parts(decoder("deflate"), &encoded, 3).unwrap());
+        }
+
+        let headers = HeaderMap::from(vec![("Content-Encoding".to_string(), "br".to_string())]);
+        assert!(ContentDecoder::for_headers(&headers, &ResponseLimits::default()).is_none());
+    }
+}
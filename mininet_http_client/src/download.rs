@@ -0,0 +1,252 @@
+use core::time::Duration;
+
+use alloc::{boxed::Box, format, string::{String, ToString}, vec, vec::Vec};
+use async_trait::async_trait;
+use mininet_base::stack::{SystemEnvironment, TcpStack};
+use sha2::{Digest, Sha256};
+use slog::{info, warn};
+
+use crate::{HttpClientError, client::HttpClient, interceptor::HttpInterceptor, retry::{RetryCause, is_retryable}};
+
+/// Storage for a downloaded image, like a flash partition.
+#[async_trait]
+pub trait DownloadSink: Send {
+    /// Stores the data at the offset. The data arrives in order, without gaps.
+    async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), HttpClientError>;
+
+    /// Reads back stored data. Used to hash the part kept from an earlier
+    /// run when a download is resumed with a saved [`DownloadState`].
+    async fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, HttpClientError>;
+
+    /// Drops the stored data, the download starts over because the image
+    /// changed on the server.
+    async fn reset(&mut self) -> Result<(), HttpClientError>;
+}
+
+#[async_trait]
+impl DownloadSink for Vec<u8> {
+    async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), HttpClientError> {
+        self.truncate(offset as usize);
+        self.extend_from_slice(data);
+        Ok(())
+    }
+
+    async fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, HttpClientError> {
+        let data = self.get(offset as usize..).unwrap_or(&[]);
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    async fn reset(&mut self) -> Result<(), HttpClientError> {
+        self.clear();
+        Ok(())
+    }
+}
+
+/// Progress of a download. It can be persisted, to continue after a restart.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadState {
+    /// Bytes stored in the sink.
+    pub offset: u64,
+    /// The size of the image, once known.
+    pub total: Option<u64>,
+    /// The strong `ETag` or the `Last-Modified` of the image, a resumed
+    /// transfer is only continued if it still matches.
+    pub validator: Option<String>
+}
+
+/// A resumable download of a large file, like a firmware image, straight
+/// into a [`DownloadSink`].
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub url: String,
+    /// Checked once the whole image is stored.
+    pub sha256: Option<[u8; 32]>,
+    /// The largest piece written to the sink at once.
+    pub chunk_size: usize,
+    /// Interrupted transfers are continued with a `Range` request this many times.
+    pub max_resumes: usize,
+    /// The wait before continuing an interrupted transfer.
+    pub resume_delay: Duration,
+    pub state: DownloadState
+}
+
+impl Download {
+    pub fn new(url: &str) -> Self {
+        Download {
+            url: url.to_string(),
+            sha256: None,
+            chunk_size: 1024,
+            max_resumes: 5,
+            resume_delay: Duration::from_secs(1),
+            state: DownloadState::default()
+        }
+    }
+
+    pub fn with_sha256(mut self, sha256: [u8; 32]) -> Self {
+        self.sha256 = Some(sha256);
+        self
+    }
+
+    /// Continues a download saved earlier, the sink must still hold the data.
+    pub fn with_state(mut self, state: DownloadState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state.total == Some(self.state.offset)
+    }
+}
+
+impl<S, E, I> HttpClient<S, E, I>
+    where S: TcpStack, E: SystemEnvironment, I: HttpInterceptor
+{
+    /// Downloads the file into the sink, continuing where the transfer
+    /// stopped after interruptions. Returns the size of the file.
+    pub async fn download<K>(&mut self, download: &mut Download, sink: &mut K) -> Result<u64, HttpClientError>
+        where K: DownloadSink, S: Send, E: Send + Sync
+    {
+        let mut hasher = Sha256::new();
+        if download.state.offset > 0 && download.sha256.is_some() && !hash_stored(sink, download.state.offset, &mut hasher).await? {
+            warn!(self.logger(), "The stored part of the download is shorter than expected, starting over.");
+            download.state = DownloadState::default();
+            hasher = Sha256::new();
+        }
+
+        let mut resumes = 0;
+        loop {
+            match self.download_attempt(download, sink, &mut hasher).await {
+                Ok(()) => break,
+                Err(ref e) if resumes < download.max_resumes && is_retryable(&RetryCause::Error(e)) => {
+                    resumes += 1;
+                    info!(self.logger(), "Download interrupted at {} bytes ({:?}), resuming.", download.state.offset, e);
+                    self.env().timeout(download.resume_delay).await;
+                },
+                Err(e) => return Err(e)
+            }
+        }
+
+        if let Some(expected) = download.sha256 {
+            if hasher.finalize().as_slice() != expected {
+                return Err(HttpClientError::ChecksumMismatch);
+            }
+        }
+        Ok(download.state.offset)
+    }
+
+    async fn download_attempt<K>(&mut self, download: &mut Download, sink: &mut K, hasher: &mut Sha256) -> Result<(), HttpClientError>
+        where K: DownloadSink, S: Send, E: Send + Sync
+    {
+        if download.is_complete() {
+            return Ok(());
+        }
+
+        let state = &mut download.state;
+        // compressed data can't be resumed at an offset
+        let mut request = self.get(&download.url).header("Accept-Encoding", "identity");
+        let resume = state.offset > 0 && state.validator.is_some();
+        if let (true, Some(validator)) = (resume, &state.validator) {
+            request = request
+                .header("Range", &format!("bytes={}-", state.offset))
+                .header("If-Range", validator);
+        }
+        let mut response = request.send_streaming().await?;
+
+        match response.code {
+            206 if resume => {
+                let (start, total) = response.header("Content-Range")
+                    .and_then(parse_content_range)
+                    .ok_or(HttpClientError::InvalidContentRange)?;
+                if start != state.offset {
+                    return Err(HttpClientError::InvalidContentRange);
+                }
+                state.total = total.or(state.total);
+            },
+            // the image changed or ranges aren't supported
+            200 => {
+                if state.offset > 0 {
+                    sink.reset().await?;
+                    *hasher = Sha256::new();
+                }
+                let etag = response.header("ETag").filter(|e| !e.starts_with("W/"));
+                *state = DownloadState {
+                    offset: 0,
+                    total: response.header("Content-Length").and_then(|l| l.trim().parse().ok()),
+                    validator: etag.or(response.header("Last-Modified")).map(|v| v.to_string())
+                };
+            },
+            // everything was received, only the end of the last response was missed
+            416 if resume && state.total == Some(state.offset) => return Ok(()),
+            code => return Err(HttpClientError::FailedStatusCode(code))
+        }
+
+        let mut buf = vec![0; download.chunk_size.max(1)];
+        loop {
+            let n = response.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            sink.write(state.offset, &buf[..n]).await?;
+            hasher.update(&buf[..n]);
+            state.offset += n as u64;
+        }
+
+        match state.total {
+            Some(total) if total != state.offset => Err(HttpClientError::IncompleteResponse),
+            _ => {
+                state.total = Some(state.offset);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Feeds the stored data into the hasher, `false` if the sink holds less.
+async fn hash_stored<K>(sink: &mut K, len: u64, hasher: &mut Sha256) -> Result<bool, HttpClientError>
+    where K: DownloadSink
+{
+    let mut buf = vec![0; 512];
+    let mut offset = 0;
+    while offset < len {
+        let want = (len - offset).min(buf.len() as u64) as usize;
+        let n = sink.read(offset, &mut buf[..want]).await?;
+        if n == 0 {
+            return Ok(false);
+        }
+        hasher.update(&buf[..n]);
+        offset += n as u64;
+    }
+    Ok(true)
+}
+
+/// The first byte and the total size of `bytes 100-199/1000`.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim().parse::<u64>().ok()?, end.trim().parse::<u64>().ok()?);
+
+    let total = match total.trim() {
+        "*" => None,
+        t => Some(t.parse::<u64>().ok()?)
+    };
+    if end < start || total.is_some_and(|t| end >= t) {
+        return None;
+    }
+    Some((start, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_range() {
+        assert_eq!(Some((100, Some(1000))), parse_content_range("bytes 100-999/1000"));
+        assert_eq!(Some((0, None)), parse_content_range("bytes 0-49/*"));
+        assert_eq!(None, parse_content_range("bytes 100-1000/1000"));
+        assert_eq!(None, parse_content_range("bytes */1000"));
+        assert_eq!(None, parse_content_range("items 1-2/3"));
+    }
+}
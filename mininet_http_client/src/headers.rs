@@ -0,0 +1,92 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+
+/// Response headers in the order they were received. Names are compared
+/// case-insensitively and a name can appear more than once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// First value of the header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// All the values of the header, in the received order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a value, keeping the existing ones.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the values of the header.
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn extend(&mut self, other: HeaderMap) {
+        self.entries.extend(other.entries);
+    }
+}
+
+impl From<Vec<(String, String)>> for HeaderMap {
+    fn from(entries: Vec<(String, String)>) -> Self {
+        HeaderMap { entries }
+    }
+}
+
+impl From<HeaderMap> for Vec<(String, String)> {
+    fn from(headers: HeaderMap) -> Self {
+        headers.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn header_map_lookup() {
+        let mut headers = HeaderMap::from(vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Set-Cookie".to_string(), "a=1".to_string())
+        ]);
+        headers.append("set-cookie", "b=2");
+
+        assert_eq!(Some("application/json"), headers.get("content-type"));
+        assert_eq!(vec!["a=1", "b=2"], headers.get_all("SET-COOKIE").collect::<Vec<_>>());
+        assert!(!headers.contains("Location"));
+        assert_eq!(3, headers.len());
+        assert_eq!(Some(("set-cookie", "b=2")), headers.iter().last());
+    }
+}
@@ -0,0 +1,174 @@
+use core::ops::Add;
+use alloc::boxed::Box;
+use async_trait::async_trait;
+
+use crate::{HttpClientError, request::HttpRequest, response::Response};
+
+/// Runs around the requests sent by the client, for things like
+/// authentication, signing, logging, metrics or mocked responses.
+///
+/// For streaming requests, and the downloads and event sources built on
+/// them, `intercept` sees the response without its body, which is read
+/// afterwards. A multipart request is seen without its body, and its form
+/// can only be sent once.
+#[async_trait]
+pub trait HttpInterceptor: Send {
+    /// Adjusts the request before it is sent. Runs for every request,
+    /// including the streaming ones.
+    fn prepare(&mut self, _request: &mut HttpRequest) -> Result<(), HttpClientError> {
+        Ok(())
+    }
+
+    /// Runs around a request. The request is sent by passing it to `next`,
+    /// the interceptor can also return a response on its own.
+    async fn intercept<N>(&mut self, request: HttpRequest, next: N) -> Result<Response, HttpClientError>
+        where N: HttpInterceptorRunner
+    {
+        next.run(request).await
+    }
+}
+
+/// The rest of the chain, ending with the network.
+#[async_trait]
+pub trait HttpInterceptorRunner: Send + Sized {
+    async fn run(self, request: HttpRequest) -> Result<Response, HttpClientError>;
+}
+
+/// Passes the requests on unchanged.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Null;
+
+impl HttpInterceptor for Null { }
+
+/// Interceptors composed in order, `head` runs first.
+pub struct Chain<H, T>
+    where H: HttpInterceptor,
+        T: HttpInterceptor
+{
+    head: H,
+    tail: T
+}
+
+impl<H> Chain<H, Null>
+    where H: HttpInterceptor
+{
+    pub fn new(interceptor: H) -> Self {
+        Chain {
+            head: interceptor,
+            tail: Null
+        }
+    }
+}
+
+impl<H, T> Chain<H, T>
+    where H: HttpInterceptor,
+        T: HttpInterceptor
+{
+    pub fn chain<N>(self, with: N) -> <Self as Add<Chain<N, Null>>>::Output
+        where
+            N: HttpInterceptor,
+            Self: Add<Chain<N, Null>>
+    {
+        self + Chain::new(with)
+    }
+}
+
+impl<H> From<H> for Chain<H, Null> where H: HttpInterceptor {
+    fn from(interceptor: H) -> Self {
+        Chain::new(interceptor)
+    }
+}
+
+#[async_trait]
+impl<H, T> HttpInterceptor for Chain<H, T>
+    where H: HttpInterceptor,
+        T: HttpInterceptor
+{
+    fn prepare(&mut self, request: &mut HttpRequest) -> Result<(), HttpClientError> {
+        self.head.prepare(request)?;
+        self.tail.prepare(request)
+    }
+
+    async fn intercept<N>(&mut self, request: HttpRequest, next: N) -> Result<Response, HttpClientError>
+        where N: HttpInterceptorRunner
+    {
+        self.head.intercept(request, Link { interceptor: &mut self.tail, next }).await
+    }
+}
+
+/// Runs an interceptor with the rest of the chain after it.
+struct Link<'a, T, N> {
+    interceptor: &'a mut T,
+    next: N
+}
+
+#[async_trait]
+impl<'a, T, N> HttpInterceptorRunner for Link<'a, T, N>
+    where T: HttpInterceptor,
+        N: HttpInterceptorRunner
+{
+    async fn run(self, request: HttpRequest) -> Result<Response, HttpClientError> {
+        self.interceptor.intercept(request, self.next).await
+    }
+}
+
+pub trait ChainElement { }
+
+impl<H, T> ChainElement for Chain<H, T>
+    where H: HttpInterceptor,
+        T: HttpInterceptor
+{
+
+}
+
+impl ChainElement for Null {
+
+}
+
+impl<RHS> Add<RHS> for Null
+    where RHS: ChainElement
+{
+    type Output = RHS;
+
+    fn add(self, rhs: RHS) -> RHS {
+        rhs
+    }
+}
+
+impl<H, T, RHS> Add<RHS> for Chain<H, T>
+    where H: HttpInterceptor,
+        T: HttpInterceptor + Add<RHS>,
+        <T as Add<RHS>>::Output: HttpInterceptor
+{
+    type Output = Chain<H, <T as Add<RHS>>::Output>;
+
+    fn add(self, rhs: RHS) -> Self::Output {
+        Chain {
+            head: self.head,
+            tail: self.tail + rhs
+        }
+    }
+}
+
+/// An interceptor that only adjusts the requests, like adding a header.
+pub struct HttpInterceptorFn<F>
+    where F: FnMut(&mut HttpRequest) -> Result<(), HttpClientError> + Send
+{
+    func: F
+}
+
+impl<F> HttpInterceptorFn<F>
+    where F: FnMut(&mut HttpRequest) -> Result<(), HttpClientError> + Send
+{
+    pub fn new(func: F) -> Self {
+        HttpInterceptorFn { func }
+    }
+}
+
+impl<F> HttpInterceptor for HttpInterceptorFn<F>
+    where F: FnMut(&mut HttpRequest) -> Result<(), HttpClientError> + Send
+{
+    fn prepare(&mut self, request: &mut HttpRequest) -> Result<(), HttpClientError> {
+        (self.func)(request)
+    }
+}
@@ -1,11 +1,62 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod auth;
+pub mod client;
+pub mod cookie;
+mod date;
+pub mod decode;
+pub mod download;
+pub mod headers;
+pub mod interceptor;
+pub mod multipart;
+pub mod oauth;
+pub mod pool;
+pub mod proxy;
+pub mod queue;
+pub mod quick_rest;
+pub mod redirect;
+pub mod request;
+pub mod response;
+pub mod retry;
+pub mod sse;
+pub mod stream;
+pub mod timeout;
+
+pub mod chunked {
+    pub use mininet_base::chunked::ChunkedDecoder;
+}
 
-use alloc::{format, string::{String, ToString}, vec::Vec, vec};
-use mininet_base::{addr::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4}, stack::{TcpError, TcpSocket, TcpStack}};
+use alloc::string::String;
+use mininet_base::{chunked::ChunkedError, stack::{TcpError, TcpStack}};
 use slog::{Logger, info};
 
+pub use auth::Auth;
+pub use client::HttpClient;
+pub use cookie::{Cookie, CookieJar};
+pub use download::{Download, DownloadSink, DownloadState};
+pub use headers::HeaderMap;
+pub use interceptor::{Chain, HttpInterceptor, HttpInterceptorFn, HttpInterceptorRunner};
+pub use multipart::{Multipart, Part, PartSource};
+pub use oauth::{AccessToken, TokenManager};
+pub use pool::PoolConfig;
+pub use proxy::Proxy;
+pub use queue::{MemoryStorage, OutboundQueue, QueueStorage, QueuedRequest};
+#[cfg(feature = "std")]
+pub use queue::FileStorage;
+pub use quick_rest::{QuickRestClient, QuickRestValueInfo};
+pub use redirect::{Redirect, RedirectPolicy};
+pub use request::{HttpRequest, RequestBuilder};
+pub use response::{Response, ResponseError, ResponseLimits};
+pub use retry::{RetryCause, RetryPolicy};
+pub use sse::{Event, EventSource};
+pub use stream::StreamingResponse;
+pub use timeout::Timeouts;
+pub use mininet_base::req::HttpMethod;
+
 
 #[derive(Debug)]
 pub enum HttpClientError {
@@ -14,10 +65,60 @@ pub enum HttpClientError {
     HttpParseError(httparse::Error),
     IncompleteResponse,
     MissingReponseCode,
+    /// Returned by `error_for_status` for a status code outside of 2xx.
     FailedStatusCode(u16),
     UrlParseError,
     UrlPortParseError,
-    UnsupportedUrlScheme(String)
+    UnsupportedUrlScheme(String),
+    /// A header name or value with a line break, which would start a header
+    /// of its own, or a name that is empty or contains a colon.
+    InvalidHeader,
+    SerializeError,
+    /// A JSON body didn't match the expected type.
+    DeserializeError(serde_json::Error),
+    InvalidChunkedEncoding,
+    InvalidContentLength,
+    /// The response has more headers or trailers than `ResponseLimits::max_headers`.
+    TooManyHeaders,
+    /// The response head is longer than `ResponseLimits::max_header_bytes`.
+    HeadersTooLarge,
+    /// The body is longer than `ResponseLimits::max_body_bytes`.
+    BodyTooLarge,
+    /// A compressed body couldn't be decoded.
+    InvalidContentEncoding,
+    /// A multipart source produced a different amount of data than it announced.
+    InvalidPartLength,
+    /// A ranged response didn't continue where the download stopped.
+    InvalidContentRange,
+    /// A downloaded file didn't match its SHA-256.
+    ChecksumMismatch,
+    /// A download sink couldn't store the data.
+    SinkError,
+    TooManyRedirects,
+    /// A redirect from `https` to `http` was refused.
+    InsecureRedirect,
+    InvalidRedirectLocation,
+    /// The proxy refused to open a tunnel, with this status code.
+    ProxyConnectFailed(u16),
+    /// The token endpoint refused to issue an access token, with this status code.
+    TokenRequestFailed(u16),
+    /// The token endpoint answered with something other than a bearer token.
+    InvalidTokenResponse,
+    /// An event source was answered with another `Content-Type` than `text/event-stream`.
+    NotAnEventStream,
+    /// An event, or a line of the event stream, is longer than the event source allows.
+    EventTooLarge,
+    /// A queue storage couldn't store or load an entry.
+    StorageError,
+    /// The outbound queue holds as many entries as it is allowed to.
+    QueueFull,
+    ConnectTimeout,
+    /// The server didn't start responding in time.
+    FirstByteTimeout,
+    /// The server stopped sending the response.
+    ReadTimeout,
+    /// The whole request took too long.
+    Timeout
 }
 
 impl From<TcpError> for HttpClientError {
@@ -26,6 +127,16 @@ impl From<TcpError> for HttpClientError {
     }
 }
 
+impl From<ChunkedError> for HttpClientError {
+    fn from(e: ChunkedError) -> Self {
+        match e {
+            ChunkedError::Invalid | ChunkedError::ChunkTooLarge => Self::InvalidChunkedEncoding,
+            ChunkedError::BodyTooLarge => Self::BodyTooLarge,
+            ChunkedError::TooManyTrailers => Self::TooManyHeaders
+        }
+    }
+}
+
 impl From<httparse::Error> for HttpClientError {
     fn from(e: httparse::Error) -> Self {
         Self::HttpParseError(e)
@@ -35,7 +146,7 @@ impl From<httparse::Error> for HttpClientError {
 pub async fn http_get<S>(logger: &Logger, stack: &mut S, url: &str) -> Result<Response, HttpClientError>
     where S: TcpStack
 {
-    let url_parsed = mininet_base::url::Url::parse(url).map_err(|e| HttpClientError::UrlParseError)?.1;
+    let url_parsed = mininet_base::url::Url::parse(url).map_err(|_| HttpClientError::UrlParseError)?.1;
     info!(logger, "Url: {:?}", url_parsed);
     
     let port = match (url_parsed.port, url_parsed.scheme.as_str()) {
@@ -44,103 +155,18 @@ pub async fn http_get<S>(logger: &Logger, stack: &mut S, url: &str) -> Result<Re
         _ => Err(HttpClientError::UrlPortParseError)
     }?;
 
-    let socket_addr = match url_parsed.authority {
-        mininet_base::url::Authority::Hostname(ref h) => {
-            stack.get_socket_address(&format!("{}:{}", h, port)).await?
-        },
-        mininet_base::url::Authority::Ip((a, b, c, d)) => {
-            let ip = Ipv4Addr::new(a, b, c, d);
-            SocketAddrV4::new(ip, port).into()
-        },
-    };
+    let socket_addr = client::resolve(stack, &url_parsed, port).await?;
 
     info!(logger, "Socket address: {:?}", socket_addr);
     
     let mut socket = stack.create_socket_connected(socket_addr).await?;
-    
-    let host = match url_parsed.authority {
-        mininet_base::url::Authority::Hostname(ref h) => h.clone(),
-        mininet_base::url::Authority::Ip(ip) => {
-            let host: Vec<_> = [ip.0, ip.1, ip.2, ip.3].iter().map(|o| o.to_string()).collect();
-            host.join(".")
-        },
-    };
-
-    let path = match url_parsed.path {
-        Some(p) => p,
-        None => "/".into(),
-    };
-
-    let http_get = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
-    info!(logger, "HTTP sending: {}", http_get);
-
-    socket.send(http_get.as_bytes()).await?;
-    
-    let buf = socket.read_to_end().await?;
-    info!(logger, "Received data len: {}", buf.len());
-
-    drop(socket);
-
-    let mut headers_buffer = [httparse::EMPTY_HEADER; 60];
-
-    let mut r = httparse::Response::new(&mut headers_buffer);
-    let n = match r.parse(buf.as_slice()) {
-        Ok(httparse::Status::Complete(size)) => {
-            size
-        },
-        Ok(httparse::Status::Partial) => {
-            return Err(HttpClientError::IncompleteResponse);
-        }
-        Err(e) => {
-            return Err(e.into());
-        }
-    };
-    let body = &buf[n..];
 
-    let status_code = r.code.ok_or(HttpClientError::MissingReponseCode)?;
-    if (status_code >= 200 && status_code <= 299) == false {
-        return Err(HttpClientError::FailedStatusCode(status_code));
-    }
-
-    info!(logger, "Headers: {:?}", r.headers);
-    if let Ok(s) = alloc::str::from_utf8(body) {
-        info!(logger, "Body as string: {}", s);
-    }
-
-    let headers = r.headers.iter().filter_map(|h| {
-        let v = alloc::str::from_utf8(h.value);
-
-        match v {
-            Ok(v) => Some((h.name.to_string(), v.to_string())),
-            _ => None
-        }
-    }).collect();
-
-    let resp = Response {
-        headers,
-        body: body.to_vec()
-    };
-    Ok(resp)
-}
+    let mut request = HttpRequest::new(HttpMethod::Get, url_parsed);
+    request.set_header("Connection", "close");
+    let http_get = request.to_http_head();
+    info!(logger, "HTTP sending: {}", http_get);
 
-#[derive(Debug)]
-pub struct Response {
-    pub headers: Vec<(String, String)>,
-    pub body: Vec<u8>
-}
+    client::send_all(&mut socket, http_get.as_bytes()).await?;
 
-#[derive(Debug)]
-pub enum ResponseError {
-    DeserializeError
+    response::read_response(logger, &mut socket, HttpMethod::Get, &ResponseLimits::default()).await
 }
-
-impl Response {
-    pub fn from_json<'a, T>(&'a self) -> Result<T, ResponseError>
-        where T: serde::Deserialize<'a>
-    {
-        match serde_json::from_slice(self.body.as_slice()) {
-            Ok(data) => Ok(data),
-            Err(_) => Err(ResponseError::DeserializeError)
-        }
-    }
-}
\ No newline at end of file
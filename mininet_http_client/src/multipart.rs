@@ -0,0 +1,270 @@
+use alloc::{boxed::Box, format, string::{String, ToString}, vec, vec::Vec};
+use async_trait::async_trait;
+use mininet_base::stack::TcpSocket;
+
+use crate::{HttpClientError, client::send_all};
+
+const UPLOAD_BUFFER_SIZE: usize = 1024;
+
+/// Data of a part that is read while the request is sent, like a file on
+/// the flash or a camera frame.
+#[async_trait]
+pub trait PartSource: Send {
+    /// Reads the next piece of the data into `buf`. Returns 0 at the end.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, HttpClientError>;
+
+    /// The exact number of bytes the source produces, if known upfront.
+    /// When every part has a known size, the body is sent with a
+    /// `Content-Length`, otherwise it is chunked.
+    fn content_length(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[async_trait]
+impl PartSource for &[u8] {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, HttpClientError> {
+        let n = self.len().min(buf.len());
+        buf[..n].copy_from_slice(&self[..n]);
+        *self = &self[n..];
+        Ok(n)
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+}
+
+enum PartData<'a> {
+    Bytes(Vec<u8>),
+    Stream(Box<dyn PartSource + 'a>)
+}
+
+/// A single field of a form.
+pub struct Part<'a> {
+    data: PartData<'a>,
+    file_name: Option<String>,
+    mime: Option<String>
+}
+
+impl<'a> Part<'a> {
+    pub fn text(value: &str) -> Self {
+        Self::bytes(value.as_bytes())
+    }
+
+    pub fn bytes<B>(data: B) -> Self
+        where B: Into<Vec<u8>>
+    {
+        Part {
+            data: PartData::Bytes(data.into()),
+            file_name: None,
+            mime: None
+        }
+    }
+
+    /// The data is read from the source while the request is sent.
+    pub fn stream<R>(source: R) -> Self
+        where R: PartSource + 'a
+    {
+        Part {
+            data: PartData::Stream(Box::new(source)),
+            file_name: None,
+            mime: None
+        }
+    }
+
+    pub fn file_name(mut self, file_name: &str) -> Self {
+        self.file_name = Some(file_name.to_string());
+        self
+    }
+
+    /// The `Content-Type` of the part. Line breaks are removed, they would
+    /// start a header of their own.
+    pub fn mime(mut self, mime: &str) -> Self {
+        self.mime = Some(mime.chars().filter(|c| *c != '\r' && *c != '\n').collect());
+        self
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        match self.data {
+            PartData::Bytes(ref b) => Some(b.len() as u64),
+            PartData::Stream(ref s) => s.content_length()
+        }
+    }
+}
+
+/// A `multipart/form-data` body. The streamed parts are read only once, so
+/// these requests are not retried and redirects that would send the body
+/// again are returned as responses.
+pub struct Multipart<'a> {
+    boundary: Option<String>,
+    parts: Vec<(String, Part<'a>)>
+}
+
+impl<'a> Multipart<'a> {
+    /// The boundary is generated by the client when the form is sent.
+    pub fn new() -> Self {
+        Multipart {
+            boundary: None,
+            parts: vec![]
+        }
+    }
+
+    /// The boundary must not appear in any of the parts.
+    pub fn with_boundary(boundary: &str) -> Self {
+        Multipart {
+            boundary: Some(boundary.to_string()),
+            parts: vec![]
+        }
+    }
+
+    pub fn text(self, name: &str, value: &str) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    pub fn part(mut self, name: &str, part: Part<'a>) -> Self {
+        self.parts.push((name.to_string(), part));
+        self
+    }
+
+    pub(crate) fn boundary_or_else<F>(&mut self, generate: F) -> &str
+        where F: FnOnce() -> String
+    {
+        self.boundary.get_or_insert_with(generate)
+    }
+
+    pub fn content_type(&self) -> Option<String> {
+        self.boundary.as_ref().map(|b| format!("multipart/form-data; boundary={}", b))
+    }
+
+    /// The size of the whole body, `None` if a part has an unknown size.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut len = self.closing().len() as u64;
+        for (name, part) in &self.parts {
+            len += self.part_head(name, part).len() as u64 + part.content_length()? + 2;
+        }
+        Some(len)
+    }
+
+    fn part_head(&self, name: &str, part: &Part<'a>) -> String {
+        let mut head = format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", self.boundary.as_deref().unwrap_or(""), escape(name));
+        if let Some(ref file_name) = part.file_name {
+            head.push_str(&format!("; filename=\"{}\"", escape(file_name)));
+        }
+        if let Some(ref mime) = part.mime {
+            head.push_str(&format!("\r\nContent-Type: {}", mime));
+        }
+        head.push_str("\r\n\r\n");
+        head
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary.as_deref().unwrap_or(""))
+    }
+
+    /// Sends the body, with the chunked transfer coding if `chunked` is set.
+    pub(crate) async fn write_to<T>(&mut self, socket: &mut T, chunked: bool) -> Result<(), HttpClientError>
+        where T: TcpSocket
+    {
+        let mut writer = BodyWriter { socket, chunked };
+        let mut buf = vec![0; UPLOAD_BUFFER_SIZE];
+
+        let heads: Vec<String> = self.parts.iter().map(|(name, part)| self.part_head(name, part)).collect();
+        for ((_, part), head) in self.parts.iter_mut().zip(heads) {
+            writer.write(head.as_bytes()).await?;
+
+            match part.data {
+                PartData::Bytes(ref data) => writer.write(data).await?,
+                PartData::Stream(ref mut source) => {
+                    let expected = source.content_length();
+                    let mut sent = 0;
+                    loop {
+                        let n = source.read(&mut buf).await?;
+                        if n == 0 {
+                            break;
+                        }
+                        writer.write(&buf[..n]).await?;
+                        sent += n as u64;
+                    }
+                    // the announced Content-Length would be wrong
+                    if !chunked && expected != Some(sent) {
+                        return Err(HttpClientError::InvalidPartLength);
+                    }
+                }
+            }
+
+            writer.write(b"\r\n").await?;
+        }
+
+        writer.write(self.closing().as_bytes()).await?;
+        if chunked {
+            send_all(writer.socket, b"0\r\n\r\n").await?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Default for Multipart<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct BodyWriter<'s, T> {
+    socket: &'s mut T,
+    chunked: bool
+}
+
+impl<'s, T> BodyWriter<'s, T> where T: TcpSocket {
+    async fn write(&mut self, data: &[u8]) -> Result<(), HttpClientError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            send_all(self.socket, format!("{:x}\r\n", data.len()).as_bytes()).await?;
+            send_all(self.socket, data).await?;
+            send_all(self.socket, b"\r\n").await?;
+        } else {
+            send_all(self.socket, data).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Quotes are percent-encoded and line breaks dropped, as browsers do.
+fn escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| *c != '\r' && *c != '\n')
+        .map(|c| if c == '"' { "%22".to_string() } else { c.to_string() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multipart_length() {
+        let data = [7u8; 3000];
+        let form = Multipart::with_boundary("xyz")
+            .text("device", "cam-01")
+            .part("image", Part::stream(&data[..]).file_name("snap\"1\".jpg").mime("image/jpeg"));
+
+        assert_eq!(Some("multipart/form-data; boundary=xyz".to_string()), form.content_type());
+        let heads = form.part_head("device", &form.parts[0].1) + &form.part_head("image", &form.parts[1].1);
+        assert!(heads.contains("name=\"image\"; filename=\"snap%221%22.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n"));
+        assert_eq!(Some((heads.len() + 6 + 2 + 3000 + 2 + "--xyz--\r\n".len()) as u64), form.content_length());
+
+        let part = Part::text("a").mime("text/plain; charset=\"utf-8\"\r\nX-Injected: 1");
+        assert!(form.part_head("note", &part).ends_with("\r\nContent-Type: text/plain; charset=\"utf-8\"X-Injected: 1\r\n\r\n"));
+
+        struct Unknown;
+        #[async_trait]
+        impl PartSource for Unknown {
+            async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, HttpClientError> {
+                Ok(0)
+            }
+        }
+        assert_eq!(None, form.part("log", Part::stream(Unknown)).content_length());
+    }
+}
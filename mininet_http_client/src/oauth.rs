@@ -0,0 +1,185 @@
+use core::time::Duration;
+
+use alloc::{format, string::{String, ToString}};
+use mininet_base::url::{Url, encode_component};
+use serde::Deserialize;
+
+use crate::{HttpClientError, auth::basic_authorization};
+
+/// An access token with its expiry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessToken {
+    pub access_token: String,
+    /// Seconds since the Unix epoch when the token is refreshed, `None`
+    /// without a clock or an `expires_in`.
+    pub refresh_at: Option<u64>,
+    /// Time from the token response until the token is refreshed, `None`
+    /// without an `expires_in`.
+    pub refresh_in: Option<Duration>
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: Option<u64>
+}
+
+/// Fetches and caches the access tokens of the OAuth2 client credentials
+/// grant, RFC 6749 section 4.4, for the client's requests.
+///
+/// A token is fetched before the first request and again shortly before it
+/// expires. A `401` answer to a request with the token also fetches a new
+/// one, and the request is sent again once.
+#[derive(Clone)]
+pub struct TokenManager {
+    pub token_url: Url,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+    credentials_in_body: bool,
+    refresh_margin: Duration,
+    now: Option<fn() -> u64>,
+    token: Option<AccessToken>
+}
+
+impl TokenManager {
+    pub fn client_credentials(token_url: &str, client_id: &str, client_secret: &str) -> Result<Self, HttpClientError> {
+        let token_url = Url::parse(token_url).map_err(|_| HttpClientError::UrlParseError)?.1;
+
+        Ok(TokenManager {
+            token_url,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scope: None,
+            credentials_in_body: false,
+            refresh_margin: Duration::from_secs(30),
+            now: None,
+            token: None
+        })
+    }
+
+    /// Space separated scopes requested with the token.
+    pub fn with_scope(mut self, scope: &str) -> Self {
+        self.scope = Some(scope.to_string());
+        self
+    }
+
+    /// Sends the client id and the secret as form fields, for servers that
+    /// don't support them in a `Basic` header.
+    pub fn with_credentials_in_body(mut self) -> Self {
+        self.credentials_in_body = true;
+        self
+    }
+
+    /// Current time in seconds since the Unix epoch. Without a clock, the
+    /// client times the expiry of its token with its `SystemEnvironment`.
+    pub fn with_clock(mut self, now: fn() -> u64) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// How long before the expiry a token is replaced, at most half of its lifetime.
+    pub fn with_refresh_margin(mut self, margin: Duration) -> Self {
+        self.refresh_margin = margin;
+        self
+    }
+
+    /// The cached token, while it is still valid.
+    pub fn token(&self) -> Option<&AccessToken> {
+        let now = self.now.map(|now| now());
+        self.token.as_ref().filter(|t| match (t.refresh_at, now) {
+            (Some(refresh_at), Some(now)) => now < refresh_at,
+            _ => true
+        })
+    }
+
+    /// Drops the cached token, the next request fetches a new one.
+    pub fn invalidate(&mut self) {
+        self.token = None;
+    }
+
+    /// The `Authorization` and the form of a token request.
+    pub(crate) fn token_request(&self) -> (Option<String>, String) {
+        let mut body = "grant_type=client_credentials".to_string();
+        if let Some(ref scope) = self.scope {
+            body.push_str(&format!("&scope={}", encode_component(scope)));
+        }
+
+        if self.credentials_in_body {
+            body.push_str(&format!("&client_id={}&client_secret={}", encode_component(&self.client_id), encode_component(&self.client_secret)));
+            return (None, body);
+        }
+        // the credentials are form encoded before they are put in the header
+        let authorization = basic_authorization(&encode_component(&self.client_id), &encode_component(&self.client_secret));
+        (Some(authorization), body)
+    }
+
+    /// Stores the token of a successful token response.
+    pub(crate) fn store(&mut self, body: &[u8]) -> Result<&AccessToken, HttpClientError> {
+        let response: TokenResponse = serde_json::from_slice(body).map_err(|_| HttpClientError::InvalidTokenResponse)?;
+        if !response.token_type.eq_ignore_ascii_case("Bearer") {
+            return Err(HttpClientError::InvalidTokenResponse);
+        }
+
+        let refresh_in = response.expires_in.map(|expires_in| {
+            let margin = self.refresh_margin.as_secs().min(expires_in / 2);
+            expires_in - margin
+        });
+        let refresh_at = refresh_in.zip(self.now).map(|(refresh_in, now)| now().saturating_add(refresh_in));
+
+        Ok(self.token.insert(AccessToken {
+            access_token: response.access_token,
+            refresh_at,
+            refresh_in: refresh_in.map(Duration::from_secs)
+        }))
+    }
+}
+
+impl core::fmt::Debug for TokenManager {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TokenManager")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_expiry() {
+        let mut manager = TokenManager::client_credentials("http://auth.local/oauth/token", "device 1", "s3cret")
+            .unwrap()
+            .with_scope("telemetry ota")
+            .with_clock(|| 1000);
+
+        let (authorization, body) = manager.token_request();
+        assert_eq!(Some(basic_authorization("device%201", "s3cret")), authorization);
+        assert_eq!("grant_type=client_credentials&scope=telemetry%20ota", body);
+
+        let token = manager.store(br#"{"access_token":"t1","token_type":"bearer","expires_in":3600}"#).unwrap();
+        assert_eq!((Some(4570), Some(Duration::from_secs(3570))), (token.refresh_at, token.refresh_in));
+        assert_eq!(Some("t1"), manager.token().map(|t| t.access_token.as_str()));
+
+        // a short lifetime is cut by half at most
+        let token = manager.store(br#"{"access_token":"t2","token_type":"Bearer","expires_in":40}"#).unwrap();
+        assert_eq!(Some(1020), token.refresh_at);
+        let token = manager.store(br#"{"access_token":"t3","token_type":"Bearer","expires_in":0}"#).unwrap();
+        assert_eq!(Some(1000), token.refresh_at);
+        assert!(manager.token().is_none());
+
+        let token = manager.store(br#"{"access_token":"t4","token_type":"Bearer"}"#).unwrap();
+        assert_eq!((None, None), (token.refresh_at, token.refresh_in));
+
+        assert!(matches!(manager.store(br#"{"access_token":"t5","token_type":"mac"}"#), Err(HttpClientError::InvalidTokenResponse)));
+        assert!(matches!(manager.store(b"{}"), Err(HttpClientError::InvalidTokenResponse)));
+
+        let (authorization, body) = manager.with_credentials_in_body().token_request();
+        assert_eq!(None, authorization);
+        assert!(body.ends_with("&client_id=device%201&client_secret=s3cret"));
+    }
+}
@@ -0,0 +1,140 @@
+use core::time::Duration;
+
+use alloc::{format, string::String, vec, vec::Vec};
+use futures::FutureExt;
+use mininet_base::{stack::{SystemEnvironment, TcpSocket}, url::Url};
+
+use crate::HttpClientError;
+
+/// Limits for the persistent connections kept by the client.
+#[derive(Debug, Copy, Clone)]
+pub struct PoolConfig {
+    /// Maximum number of sockets held by the client, including the one in use.
+    pub max_connections: usize,
+    /// Maximum number of idle connections kept for a single host.
+    pub max_idle_per_host: usize,
+    /// Idle connections are closed after this time.
+    pub idle_timeout: Duration
+}
+
+impl PoolConfig {
+    /// Every request uses a new connection, with `Connection: close`.
+    pub fn disabled() -> Self {
+        PoolConfig {
+            max_connections: 1,
+            max_idle_per_host: 0,
+            idle_timeout: Duration::from_secs(0)
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_idle_per_host > 0 && self.max_connections > 0
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 4,
+            max_idle_per_host: 1,
+            idle_timeout: Duration::from_secs(30)
+        }
+    }
+}
+
+/// Identifies connections that can be shared, `scheme://host:port`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionKey(String);
+
+impl ConnectionKey {
+    pub fn new(url: &Url) -> Result<Self, HttpClientError> {
+        let port = url.port_or_default().ok_or(HttpClientError::UrlPortParseError)?;
+        Ok(ConnectionKey(format!("{}://{}:{}", url.scheme, url.host(), port)))
+    }
+}
+
+struct IdleConnection<T, E>
+    where E: SystemEnvironment
+{
+    key: ConnectionKey,
+    socket: T,
+    expired: E::Timeout
+}
+
+impl<T, E> IdleConnection<T, E>
+    where E: SystemEnvironment
+{
+    fn is_expired(&mut self) -> bool {
+        (&mut self.expired).now_or_never().is_some()
+    }
+}
+
+/// Idle keep-alive connections, oldest first.
+pub struct ConnectionPool<T, E>
+    where T: TcpSocket, E: SystemEnvironment
+{
+    config: PoolConfig,
+    idle: Vec<IdleConnection<T, E>>
+}
+
+impl<T, E> ConnectionPool<T, E>
+    where T: TcpSocket, E: SystemEnvironment
+{
+    pub fn new(config: PoolConfig) -> Self {
+        ConnectionPool {
+            config,
+            idle: vec![]
+        }
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Takes the most recently used connection for this host that hasn't expired yet.
+    pub fn checkout(&mut self, key: &ConnectionKey) -> Option<T> {
+        self.idle.retain_mut(|c| !c.is_expired());
+
+        let pos = self.idle.iter().rposition(|c| &c.key == key)?;
+        Some(self.idle.remove(pos).socket)
+    }
+
+    /// Closes idle connections until a new socket can be opened within the limit.
+    pub fn reserve(&mut self) {
+        while !self.idle.is_empty() && self.idle.len() + 1 > self.config.max_connections {
+            self.idle.remove(0);
+        }
+    }
+
+    /// Returns a connection that can be reused for another request.
+    pub fn checkin(&mut self, env: &E, key: ConnectionKey, socket: T) {
+        if !self.config.is_enabled() {
+            return;
+        }
+
+        let for_host = self.idle.iter().filter(|c| c.key == key).count();
+        if for_host >= self.config.max_idle_per_host {
+            if let Some(pos) = self.idle.iter().position(|c| c.key == key) {
+                self.idle.remove(pos);
+            }
+        }
+
+        while !self.idle.is_empty() && self.idle.len() + 1 > self.config.max_connections {
+            self.idle.remove(0);
+        }
+
+        self.idle.push(IdleConnection {
+            key,
+            socket,
+            expired: env.timeout(self.config.idle_timeout)
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.idle.clear();
+    }
+}
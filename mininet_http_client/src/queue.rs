@@ -0,0 +1,456 @@
+use alloc::{boxed::Box, collections::VecDeque, string::{String, ToString}, vec, vec::Vec};
+use async_trait::async_trait;
+use mininet_base::{req::HttpMethod, stack::{SystemEnvironment, TcpStack}};
+use serde::{Deserialize, Serialize};
+use slog::{info, warn};
+
+use crate::{HttpClientError, auth::{base64_decode, base64_encode}, client::HttpClient, interceptor::HttpInterceptor, response::is_success, retry::{Jitter, RetryCause, RetryPolicy}};
+
+/// A request waiting in an [`OutboundQueue`], in a form that a storage can persist.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedRequest {
+    pub method: String,
+    /// Resolved against the client's base URL when the request is sent.
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    #[serde(with = "body_base64")]
+    pub body: Vec<u8>
+}
+
+/// The body as a base64 string. The arrays of numbers written by earlier
+/// versions are still read.
+mod body_base64 {
+    use alloc::{string::String, vec::Vec};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    use super::{base64_decode, base64_encode};
+
+    pub fn serialize<S>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&base64_encode(body))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Body {
+            Base64(String),
+            Bytes(Vec<u8>)
+        }
+
+        match Body::deserialize(deserializer)? {
+            Body::Base64(body) => base64_decode(&body).ok_or_else(|| D::Error::custom("invalid base64 body")),
+            Body::Bytes(body) => Ok(body)
+        }
+    }
+}
+
+impl QueuedRequest {
+    pub fn new(method: HttpMethod, url: &str) -> Self {
+        QueuedRequest {
+            method: method.to_http().to_string(),
+            url: url.to_string(),
+            headers: vec![],
+            body: vec![]
+        }
+    }
+
+    pub fn post(url: &str) -> Self {
+        Self::new(HttpMethod::Post, url)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B>(mut self, body: B) -> Self
+        where B: Into<Vec<u8>>
+    {
+        self.body = body.into();
+        self
+    }
+
+    pub fn json<T>(self, value: &T) -> Result<Self, HttpClientError>
+        where T: Serialize
+    {
+        let json = serde_json::to_vec(value).map_err(|_| HttpClientError::SerializeError)?;
+        Ok(self.header("Content-Type", "application/json").body(json))
+    }
+
+    /// The entry as bytes, for storages that keep raw records like a flash partition.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, HttpClientError> {
+        serde_json::to_vec(self).map_err(|_| HttpClientError::SerializeError)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, HttpClientError> {
+        serde_json::from_slice(data).map_err(|_| HttpClientError::StorageError)
+    }
+}
+
+/// Where the queued requests are kept until they are delivered, like a file
+/// or a flash partition that survives a reboot.
+#[async_trait]
+pub trait QueueStorage: Send {
+    /// Appends the entry at the end of the queue.
+    async fn push(&mut self, entry: &QueuedRequest) -> Result<(), HttpClientError>;
+
+    /// The oldest entry, which stays in the queue.
+    async fn front(&mut self) -> Result<Option<QueuedRequest>, HttpClientError>;
+
+    /// Removes the oldest entry, once it was delivered.
+    async fn pop_front(&mut self) -> Result<(), HttpClientError>;
+
+    async fn len(&mut self) -> Result<usize, HttpClientError>;
+
+    async fn is_empty(&mut self) -> Result<bool, HttpClientError> {
+        Ok(self.len().await? == 0)
+    }
+}
+
+/// Keeps the queue in RAM, it is lost on a reboot.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    entries: VecDeque<QueuedRequest>
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl QueueStorage for MemoryStorage {
+    async fn push(&mut self, entry: &QueuedRequest) -> Result<(), HttpClientError> {
+        self.entries.push_back(entry.clone());
+        Ok(())
+    }
+
+    async fn front(&mut self) -> Result<Option<QueuedRequest>, HttpClientError> {
+        Ok(self.entries.front().cloned())
+    }
+
+    async fn pop_front(&mut self) -> Result<(), HttpClientError> {
+        self.entries.pop_front();
+        Ok(())
+    }
+
+    async fn len(&mut self) -> Result<usize, HttpClientError> {
+        Ok(self.entries.len())
+    }
+}
+
+/// Keeps the queue in a file, one JSON entry per line. A removed entry
+/// rewrites the file through a temporary one, so a power loss leaves
+/// either the old or the new queue. A power loss while an entry is
+/// appended can leave it torn, it is dropped when the file is opened.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct FileStorage {
+    path: std::path::PathBuf,
+    entries: VecDeque<QueuedRequest>
+}
+
+#[cfg(feature = "std")]
+impl FileStorage {
+    /// Loads the entries left in the file, if it exists. A corrupt last
+    /// entry is cut from the file, one before it fails with `StorageError`.
+    pub fn open<P>(path: P) -> Result<Self, HttpClientError>
+        where P: Into<std::path::PathBuf>
+    {
+        Self::open_with_logger(&slog::Logger::root(slog::Discard, slog::o!()), path)
+    }
+
+    /// Like [`open`](Self::open), logging a dropped torn entry.
+    pub fn open_with_logger<P>(logger: &slog::Logger, path: P) -> Result<Self, HttpClientError>
+        where P: Into<std::path::PathBuf>
+    {
+        let path = path.into();
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(_) => return Err(HttpClientError::StorageError)
+        };
+
+        let mut storage = FileStorage { path, entries: VecDeque::new() };
+        let mut lines = data.split(|b| *b == b'\n').filter(|line| !line.is_empty()).peekable();
+        while let Some(line) = lines.next() {
+            match QueuedRequest::from_bytes(line) {
+                Ok(entry) => storage.entries.push_back(entry),
+                Err(_) if lines.peek().is_none() => {
+                    warn!(logger, "Dropping a torn entry at the end of the queue file {}.", storage.path.display());
+                    // the next entry would be appended to it otherwise
+                    let contents = storage.contents()?;
+                    let tmp = storage.tmp_path();
+                    std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, &storage.path))
+                        .map_err(|_| HttpClientError::StorageError)?;
+                },
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(storage)
+    }
+
+    /// The entries as the lines of the file.
+    fn contents(&self) -> Result<Vec<u8>, HttpClientError> {
+        let mut contents = vec![];
+        for entry in &self.entries {
+            contents.extend_from_slice(&entry.to_bytes()?);
+            contents.push(b'\n');
+        }
+        Ok(contents)
+    }
+
+    fn tmp_path(&self) -> std::path::PathBuf {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tmp.into()
+    }
+
+    async fn write_all(&self) -> Result<(), HttpClientError> {
+        use async_std::io::WriteExt;
+
+        let contents = self.contents()?;
+        let tmp = self.tmp_path();
+        let write = async {
+            let mut file = async_std::fs::File::create(&tmp).await?;
+            file.write_all(&contents).await?;
+            file.sync_all().await?;
+            async_std::fs::rename(&tmp, &self.path).await
+        };
+        write.await.map_err(|_| HttpClientError::StorageError)
+    }
+}
+
+#[cfg(feature = "std")]
+#[async_trait]
+impl QueueStorage for FileStorage {
+    async fn push(&mut self, entry: &QueuedRequest) -> Result<(), HttpClientError> {
+        use async_std::io::WriteExt;
+
+        let mut line = entry.to_bytes()?;
+        line.push(b'\n');
+        let append = async {
+            let mut file = async_std::fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+            file.write_all(&line).await?;
+            file.sync_data().await
+        };
+        append.await.map_err(|_| HttpClientError::StorageError)?;
+
+        self.entries.push_back(entry.clone());
+        Ok(())
+    }
+
+    async fn front(&mut self) -> Result<Option<QueuedRequest>, HttpClientError> {
+        Ok(self.entries.front().cloned())
+    }
+
+    async fn pop_front(&mut self) -> Result<(), HttpClientError> {
+        let entry = self.entries.pop_front();
+        if self.write_all().await.is_err() {
+            // still in the file, so it stays in the queue
+            if let Some(entry) = entry {
+                self.entries.push_front(entry);
+            }
+            return Err(HttpClientError::StorageError);
+        }
+        Ok(())
+    }
+
+    async fn len(&mut self) -> Result<usize, HttpClientError> {
+        Ok(self.entries.len())
+    }
+}
+
+/// Store-and-forward delivery of requests, like webhooks or telemetry, that
+/// must survive a lost connection.
+///
+/// The requests are delivered in order. An entry is removed only after a
+/// 2xx response; until then, it blocks the ones behind it. An entry the
+/// receiver refuses with a 4xx is only removed with a dead letter handler,
+/// or with [`discard_front`](Self::discard_front).
+pub struct OutboundQueue<Q>
+    where Q: QueueStorage
+{
+    storage: Q,
+    policy: RetryPolicy,
+    jitter: Jitter,
+    max_entries: usize,
+    dead_letter: Option<fn(&QueuedRequest, u16)>
+}
+
+impl<Q> OutboundQueue<Q>
+    where Q: QueueStorage
+{
+    /// Each entry is attempted up to 6 times per delivery, including `POST`s,
+    /// so the receiver should tolerate duplicates.
+    pub fn new(storage: Q) -> Self {
+        OutboundQueue {
+            storage,
+            policy: RetryPolicy {
+                max_retries: 5,
+                retry_non_idempotent: true,
+                ..Default::default()
+            },
+            jitter: Jitter::new(0),
+            max_entries: 256,
+            dead_letter: None
+        }
+    }
+
+    /// The retries of the entry at the front of the queue within one delivery.
+    /// Its `retry_non_idempotent` is ignored, a queued request is always sent again.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Requests enqueued beyond this are refused with `QueueFull`.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Seeds the backoff jitter, a per-device value keeps a fleet from retrying in lockstep.
+    pub fn with_jitter_seed(mut self, seed: u32) -> Self {
+        self.jitter = Jitter::new(seed);
+        self
+    }
+
+    /// Removes the entries refused with a 4xx status that isn't retried, and
+    /// passes them to `handler` with the status, so they don't block the queue.
+    pub fn with_dead_letter(mut self, handler: fn(&QueuedRequest, u16)) -> Self {
+        self.dead_letter = Some(handler);
+        self
+    }
+
+    pub fn storage_mut(&mut self) -> &mut Q {
+        &mut self.storage
+    }
+
+    pub fn into_storage(self) -> Q {
+        self.storage
+    }
+
+    pub async fn len(&mut self) -> Result<usize, HttpClientError> {
+        self.storage.len().await
+    }
+
+    pub async fn is_empty(&mut self) -> Result<bool, HttpClientError> {
+        self.storage.is_empty().await
+    }
+
+    /// Stores the request, it is sent by the next [`deliver`](Self::deliver).
+    pub async fn enqueue(&mut self, request: QueuedRequest) -> Result<(), HttpClientError> {
+        if self.storage.len().await? >= self.max_entries {
+            return Err(HttpClientError::QueueFull);
+        }
+        self.storage.push(&request).await
+    }
+
+    /// Removes the entry at the front of the queue without sending it, like
+    /// one the receiver will never accept.
+    pub async fn discard_front(&mut self) -> Result<Option<QueuedRequest>, HttpClientError> {
+        let entry = self.storage.front().await?;
+        if entry.is_some() {
+            self.storage.pop_front().await?;
+        }
+        Ok(entry)
+    }
+
+    /// Sends the queued requests in order until the queue is empty. Returns
+    /// the number of delivered requests.
+    ///
+    /// A failing request is retried with backoff as the policy allows. When
+    /// it still fails, or the failure isn't retryable, the delivery stops with
+    /// the error and the request stays at the front for the next delivery.
+    /// With a dead letter handler, a request refused with a 4xx is removed
+    /// instead and the delivery goes on.
+    pub async fn deliver<S, E, I>(&mut self, client: &mut HttpClient<S, E, I>) -> Result<usize, HttpClientError>
+        where S: TcpStack + Send, E: SystemEnvironment + Send + Sync, I: HttpInterceptor
+    {
+        let mut delivered = 0;
+
+        'entries: while let Some(entry) = self.storage.front().await? {
+            let method = HttpMethod::Get.from_http(&entry.method).ok_or(HttpClientError::StorageError)?;
+
+            let mut attempt = 0;
+            loop {
+                let mut request = client.request(method, &entry.url)
+                    .body(entry.body.clone())
+                    // the queue does the retrying
+                    .retry(RetryPolicy::none());
+                for (name, value) in &entry.headers {
+                    request = request.header(name, value);
+                }
+                let result = request.send().await;
+                attempt += 1;
+
+                let (cause, headers) = match result {
+                    Ok(ref r) if is_success(r.code) => break,
+                    Ok(ref r) => (RetryCause::Status(r.code), Some(&r.headers)),
+                    Err(ref e) => (RetryCause::Error(e), None)
+                };
+                warn!(client.logger(), "Delivery of the queued {} {} failed: {:?}", entry.method, entry.url, cause);
+
+                let wait = match headers.and_then(|h| self.policy.retry_after(h)) {
+                    Some(after) if after <= self.policy.max_retry_after => Some(after),
+                    Some(_) => None,
+                    None => Some(self.policy.backoff(attempt, &mut self.jitter))
+                };
+                match wait {
+                    Some(wait) if attempt <= self.policy.max_retries && (self.policy.classifier)(&cause) => {
+                        client.env().timeout(wait).await;
+                    },
+                    _ => match (result, self.dead_letter) {
+                        (Ok(r), Some(dead_letter)) if (400..500).contains(&r.code) => {
+                            warn!(client.logger(), "The queued {} {} was refused with {}, dropping it.", entry.method, entry.url, r.code);
+                            dead_letter(&entry, r.code);
+                            self.storage.pop_front().await?;
+                            continue 'entries;
+                        },
+                        (Ok(r), _) => return Err(HttpClientError::FailedStatusCode(r.code)),
+                        (Err(e), _) => return Err(e)
+                    }
+                }
+            }
+
+            self.storage.pop_front().await?;
+            delivered += 1;
+        }
+
+        if delivered > 0 {
+            info!(client.logger(), "Delivered {} queued requests.", delivered);
+        }
+        Ok(delivered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_request_bytes() {
+        let entry = QueuedRequest::post("/events")
+            .json(&[("door", "open")])
+            .unwrap();
+        assert_eq!(("POST", b"[[\"door\",\"open\"]]".as_slice()), (entry.method.as_str(), entry.body.as_slice()));
+
+        let bytes = entry.to_bytes().unwrap();
+        assert!(!bytes.contains(&b'\n'));
+        assert!(String::from_utf8_lossy(&bytes).contains("\"body\":\"W1siZG9vciIsIm9wZW4iXV0=\""));
+        assert_eq!(entry, QueuedRequest::from_bytes(&bytes).unwrap());
+        assert!(matches!(QueuedRequest::from_bytes(b"{\"method\":"), Err(HttpClientError::StorageError)));
+
+        // the body of earlier versions, an array of numbers
+        let old = QueuedRequest::from_bytes(b"{\"method\":\"POST\",\"url\":\"/e\",\"headers\":[],\"body\":[104,105]}").unwrap();
+        assert_eq!(b"hi", old.body.as_slice());
+        assert!(QueuedRequest::from_bytes(b"{\"method\":\"POST\",\"url\":\"/e\",\"headers\":[],\"body\":\"a?\"}").is_err());
+    }
+}
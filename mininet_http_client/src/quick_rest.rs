@@ -0,0 +1,186 @@
+use alloc::{collections::BTreeMap, format, string::{String, ToString}, vec::Vec};
+use mininet_base::stack::{SystemEnvironment, TcpStack};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{HttpClientError, client::HttpClient, interceptor::{HttpInterceptor, Null}};
+
+/// The JSON of a single value, `{ "value": T }`.
+#[derive(Serialize, Deserialize)]
+struct ValueDto<T> {
+    value: T
+}
+
+/// A value published by a Quick REST server, from its OpenAPI document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuickRestValueInfo {
+    pub api: String,
+    pub id: String,
+    /// The JSON schema of the value, like `{ "type": "integer" }`.
+    pub schema: Value
+}
+
+impl QuickRestValueInfo {
+    pub fn path(&self) -> String {
+        format!("{}/{}", self.api, self.id)
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenApiDocument {
+    #[serde(default)]
+    paths: BTreeMap<String, BTreeMap<String, OpenApiOperation>>
+}
+
+#[derive(Deserialize)]
+struct OpenApiOperation {
+    #[serde(rename = "requestBody")]
+    request_body: Option<OpenApiBody>
+}
+
+#[derive(Deserialize)]
+struct OpenApiBody {
+    #[serde(default)]
+    content: BTreeMap<String, OpenApiContent>
+}
+
+#[derive(Deserialize)]
+struct OpenApiContent {
+    schema: Value
+}
+
+/// The values of an OpenAPI document published by `QuickRestOpenApiMiddleware`.
+///
+/// A value is a path that takes a `{ "value": T }` object, the combined
+/// object of an API can only be read and isn't listed.
+pub fn parse_openapi(json: &[u8]) -> Result<Vec<QuickRestValueInfo>, HttpClientError> {
+    let document: OpenApiDocument = serde_json::from_slice(json).map_err(HttpClientError::DeserializeError)?;
+
+    let values = document.paths.iter()
+        .filter_map(|(path, methods)| {
+            let body = methods.get("post")?.request_body.as_ref()?;
+            let schema = body.content.get("application/json")?.schema.pointer("/properties/value")?;
+            let (api, id) = path.rsplit_once('/')?;
+
+            Some(QuickRestValueInfo { api: api.to_string(), id: id.to_string(), schema: schema.clone() })
+        })
+        .collect();
+    Ok(values)
+}
+
+/// Reads and writes the values of a device running the `quick_rest` server,
+/// each one at `<api>/<id>`.
+///
+/// The paths are resolved against the client's base URL, usually the root
+/// of the device like `http://192.168.1.20`.
+pub struct QuickRestClient<S, E, I = Null>
+    where S: TcpStack, E: SystemEnvironment, I: HttpInterceptor
+{
+    client: HttpClient<S, E, I>
+}
+
+impl<S, E, I> QuickRestClient<S, E, I>
+    where S: TcpStack + Send, E: SystemEnvironment + Send + Sync, I: HttpInterceptor
+{
+    pub fn new(client: HttpClient<S, E, I>) -> Self {
+        QuickRestClient { client }
+    }
+
+    pub fn client_mut(&mut self) -> &mut HttpClient<S, E, I> {
+        &mut self.client
+    }
+
+    pub fn into_client(self) -> HttpClient<S, E, I> {
+        self.client
+    }
+
+    /// The current value with the id in the API, like `get::<usize>("/simple", "num")`.
+    pub async fn get<T>(&mut self, api: &str, id: &str) -> Result<T, HttpClientError>
+        where T: DeserializeOwned
+    {
+        let response = self.client.get(&format!("{}/{}", api, id))
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let dto: ValueDto<T> = serde_json::from_slice(&response.body).map_err(HttpClientError::DeserializeError)?;
+        Ok(dto.value)
+    }
+
+    /// Sets a new value, a refused one is returned as a `FailedStatusCode`.
+    pub async fn set<T>(&mut self, api: &str, id: &str, value: &T) -> Result<(), HttpClientError>
+        where T: Serialize
+    {
+        self.client.post(&format!("{}/{}", api, id))
+            .json(&ValueDto { value })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// All the readable values of the API in one object, keyed by their ids.
+    /// Either a `serde_json::Map` or a struct with matching fields.
+    pub async fn get_all<T>(&mut self, api: &str) -> Result<T, HttpClientError>
+        where T: DeserializeOwned
+    {
+        let response = self.client.get(api)
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        serde_json::from_slice(&response.body).map_err(HttpClientError::DeserializeError)
+    }
+
+    /// The values of all the APIs of the server, from `/?openapi`.
+    pub async fn discover(&mut self) -> Result<Vec<QuickRestValueInfo>, HttpClientError> {
+        let response = self.client.get("/?openapi")
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        parse_openapi(&response.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use serde_json::json;
+
+    #[test]
+    fn openapi_values() {
+        let value = |ty| json!({
+            "get": { "summary": "Get the current value", "responses": {} },
+            "post": {
+                "summary": "Try to set a new value for this variable",
+                "requestBody": { "required": true, "content": { "application/json": {
+                    "schema": { "type": "object", "properties": { "value": { "type": ty } } }
+                } } },
+                "responses": {}
+            }
+        });
+        let document = json!({
+            "openapi": "3.0.0",
+            "info": { "title": "API", "description": "", "version": "0.1.0" },
+            "servers": [],
+            "paths": {
+                "/simple": { "get": { "summary": "All values", "responses": {} } },
+                "/simple/num": value("integer"),
+                "/simple/str": value("string"),
+                "/net/wifi/ssid": value("string")
+            }
+        });
+
+        let values = parse_openapi(&serde_json::to_vec(&document).unwrap()).unwrap();
+        let paths: Vec<_> = values.iter().map(|v| (v.api.as_str(), v.id.as_str(), v.schema["type"].as_str().unwrap())).collect();
+        assert_eq!(vec![("/net/wifi", "ssid", "string"), ("/simple", "num", "integer"), ("/simple", "str", "string")], paths);
+        assert_eq!("/net/wifi/ssid", values[0].path());
+
+        assert!(matches!(parse_openapi(b"<html>"), Err(HttpClientError::DeserializeError(_))));
+    }
+}
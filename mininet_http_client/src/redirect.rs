@@ -0,0 +1,119 @@
+use mininet_base::{req::HttpMethod, url::Url};
+
+use crate::{HttpClientError, request::HttpRequest};
+
+/// How 3xx responses with a `Location` header are handled.
+#[derive(Debug, Copy, Clone)]
+pub struct RedirectPolicy {
+    /// Maximum number of redirects followed for a single request, 0 disables following.
+    pub max_redirects: usize
+}
+
+impl RedirectPolicy {
+    /// Redirects are not followed.
+    pub fn none() -> Self {
+        RedirectPolicy { max_redirects: 0 }
+    }
+
+    pub fn limited(max_redirects: usize) -> Self {
+        RedirectPolicy { max_redirects }
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self::limited(5)
+    }
+}
+
+/// A redirect that was followed while executing a request.
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub status: u16,
+    /// The URL that responded with the redirect.
+    pub from: Url,
+    /// The resolved `Location`.
+    pub location: Url
+}
+
+pub(crate) fn is_redirect(code: u16) -> bool {
+    matches!(code, 301 | 302 | 303 | 307 | 308)
+}
+
+/// Builds the request for the next hop.
+pub(crate) fn redirect_request(request: &HttpRequest, status: u16, location: &str) -> Result<HttpRequest, HttpClientError> {
+    let url = request.url.join(location).ok_or(HttpClientError::InvalidRedirectLocation)?;
+
+    if request.url.scheme == "https" && url.scheme == "http" {
+        return Err(HttpClientError::InsecureRedirect);
+    }
+
+    let mut next = request.clone();
+
+    // a 303 always becomes a GET, user agents also do that for a POST with 301 and 302
+    let to_get = match status {
+        303 => request.method != HttpMethod::Head,
+        301 | 302 => request.method == HttpMethod::Post,
+        _ => false
+    };
+    if to_get {
+        next.method = HttpMethod::Get;
+        next.body.clear();
+        next.headers.retain(|(n, _)| {
+            !n.eq_ignore_ascii_case("Content-Length") && !n.eq_ignore_ascii_case("Content-Type") && !n.eq_ignore_ascii_case("Transfer-Encoding")
+        });
+    }
+
+    // credentials are only sent to the origin they were meant for
+    if url.host() != request.url.host() || url.port_or_default() != request.url.port_or_default() || url.scheme != request.url.scheme {
+        next.headers.retain(|(n, _)| !n.eq_ignore_ascii_case("Authorization") && !n.eq_ignore_ascii_case("Cookie"));
+    }
+
+    next.headers.retain(|(n, _)| !n.eq_ignore_ascii_case("Host"));
+    next.url = url;
+
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    fn request(method: HttpMethod, url: &str) -> HttpRequest {
+        let mut r = HttpRequest::new(method, Url::parse(url).unwrap().1);
+        r.body = b"data".to_vec();
+        r.headers.push(("Content-Type".to_string(), "text/plain".to_string()));
+        r.headers.push(("Authorization".to_string(), "Bearer x".to_string()));
+        r
+    }
+
+    #[test]
+    fn redirect_method_rewriting() {
+        let post = request(HttpMethod::Post, "http://a.com/form");
+
+        let r = redirect_request(&post, 303, "done").unwrap();
+        assert_eq!((HttpMethod::Get, "/done"), (r.method, r.url.path_or_root()));
+        assert!(r.body.is_empty() && r.header("Content-Type").is_none());
+        assert_eq!(Some("Bearer x"), r.header("Authorization"));
+
+        let r = redirect_request(&post, 302, "/other").unwrap();
+        assert_eq!(HttpMethod::Get, r.method);
+
+        let r = redirect_request(&post, 307, "/other").unwrap();
+        assert_eq!((HttpMethod::Post, b"data".as_slice()), (r.method, r.body.as_slice()));
+
+        let put = request(HttpMethod::Put, "http://a.com/x");
+        let r = redirect_request(&put, 301, "http://b.com/x").unwrap();
+        assert_eq!(HttpMethod::Put, r.method);
+        assert!(r.header("Authorization").is_none());
+    }
+
+    #[test]
+    fn redirect_refuses_downgrade() {
+        let get = request(HttpMethod::Get, "https://a.com/");
+        assert!(matches!(redirect_request(&get, 301, "http://a.com/"), Err(HttpClientError::InsecureRedirect)));
+        assert!(redirect_request(&get, 301, "https://b.com/").is_ok());
+    }
+}
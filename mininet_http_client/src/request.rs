@@ -0,0 +1,272 @@
+use core::time::Duration;
+
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use mininet_base::{req::HttpMethod, stack::{SystemEnvironment, TcpStack}, url::{Url, encode_component}};
+
+use crate::{HttpClientError, auth::Auth, client::HttpClient, interceptor::{HttpInterceptor, Null}, multipart::Multipart, response::Response, retry::RetryPolicy, stream::StreamingResponse, timeout::Timeouts};
+
+/// A fully resolved request, ready to be written to a socket.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    pub url: Url,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Overrides the client's limits for this request.
+    pub timeouts: Timeouts,
+    /// Overrides the client's retry policy for this request.
+    pub retry: Option<RetryPolicy>,
+    /// Overrides the client's credentials for this request.
+    pub auth: Option<Auth>
+}
+
+impl HttpRequest {
+    pub fn new(method: HttpMethod, url: Url) -> Self {
+        HttpRequest {
+            method,
+            url,
+            headers: vec![],
+            body: vec![],
+            timeouts: Timeouts::default(),
+            retry: None,
+            auth: None
+        }
+    }
+
+    /// First header with a matching name, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Replaces all the headers with this name with a single value.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Methods that carry a body get a `Content-Length`, even if it is zero.
+    fn has_body(&self) -> bool {
+        !self.body.is_empty() || matches!(self.method, HttpMethod::Post | HttpMethod::Put | HttpMethod::Patch)
+    }
+
+    /// The request line and the headers, terminated with an empty line.
+    pub fn to_http_head(&self) -> String {
+        self.http_head(self.url.path_or_root())
+    }
+
+    /// The head with the absolute URL in the request line, for a proxy.
+    pub(crate) fn to_proxy_http_head(&self) -> String {
+        let target = format!("{}://{}{}", self.url.scheme, self.host(), self.url.path_or_root());
+        self.http_head(&target)
+    }
+
+    /// The host, with the port unless it is the default one.
+    fn host(&self) -> String {
+        match (self.url.port, self.url.default_port()) {
+            (Some(port), default) if Some(port) != default => format!("{}:{}", self.url.host(), port),
+            _ => self.url.host()
+        }
+    }
+
+    fn http_head(&self, target: &str) -> String {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method.to_http(), target);
+
+        if self.header("Host").is_none() {
+            head.push_str(&format!("Host: {}\r\n", self.host()));
+        }
+
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        if self.has_body() && self.header("Content-Length").is_none() && self.header("Transfer-Encoding").is_none() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// Headers that are sent once, setting them again replaces the value.
+const SINGLE_VALUED_HEADERS: [&str; 7] = ["Authorization", "Content-Length", "Content-Type", "Host", "If-Range", "Range", "User-Agent"];
+
+/// Builds a request for [`HttpClient`]. Errors are deferred until the request is sent.
+pub struct RequestBuilder<'a, S, E, I = Null>
+    where S: TcpStack, E: SystemEnvironment
+{
+    client: &'a mut HttpClient<S, E, I>,
+    request: Result<HttpRequest, HttpClientError>,
+    query: Vec<(String, String)>
+}
+
+impl<'a, S, E, I> RequestBuilder<'a, S, E, I>
+    where S: TcpStack, E: SystemEnvironment, I: HttpInterceptor
+{
+    pub(crate) fn new(client: &'a mut HttpClient<S, E, I>, method: HttpMethod, url: &str) -> Self {
+        let request = client.resolve_url(url).map(|url| HttpRequest::new(method, url));
+
+        RequestBuilder {
+            client,
+            request,
+            query: vec![]
+        }
+    }
+
+    /// Adds a header. A header like `Content-Type` that can only be sent
+    /// once replaces the value set before.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let invalid = name.is_empty() || name.contains([':', '\r', '\n']) || value.contains(['\r', '\n']);
+        match self.request {
+            Ok(_) if invalid => self.request = Err(HttpClientError::InvalidHeader),
+            Ok(ref mut r) if SINGLE_VALUED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) => r.set_header(name, value),
+            Ok(ref mut r) => r.headers.push((name.to_string(), value.to_string())),
+            Err(_) => ()
+        }
+        self
+    }
+
+    /// Appends a percent-encoded query parameter to the URL.
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The request body, either bytes or a string. `Content-Length` is computed when sending.
+    pub fn body<B>(mut self, body: B) -> Self
+        where B: Into<Vec<u8>>
+    {
+        if let Ok(ref mut r) = self.request {
+            r.body = body.into();
+        }
+        self
+    }
+
+    pub fn json<T>(mut self, value: &T) -> Self
+        where T: serde::Serialize
+    {
+        let json = match serde_json::to_vec(value) {
+            Ok(json) => json,
+            Err(_) => {
+                self.request = Err(HttpClientError::SerializeError);
+                return self;
+            }
+        };
+
+        self.header("Content-Type", "application/json").body(json)
+    }
+
+    /// Limits the time of the whole request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        if let Ok(ref mut r) = self.request {
+            r.timeouts.total = Some(timeout);
+        }
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        if let Ok(ref mut r) = self.request {
+            r.timeouts.connect = Some(timeout);
+        }
+        self
+    }
+
+    /// Limits the wait for the first byte of the response after the request was sent.
+    pub fn first_byte_timeout(mut self, timeout: Duration) -> Self {
+        if let Ok(ref mut r) = self.request {
+            r.timeouts.first_byte = Some(timeout);
+        }
+        self
+    }
+
+    /// Limits the time between two reads of the response.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        if let Ok(ref mut r) = self.request {
+            r.timeouts.read = Some(timeout);
+        }
+        self
+    }
+
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        if let Ok(ref mut r) = self.request {
+            r.retry = Some(policy);
+        }
+        self
+    }
+
+    pub fn basic_auth(self, username: &str, password: &str) -> Self {
+        self.auth(Auth::basic(username, password))
+    }
+
+    pub fn bearer_auth(self, token: &str) -> Self {
+        self.auth(Auth::bearer(token))
+    }
+
+    /// Answers the server's `Digest` challenge, at the cost of a `401` round trip
+    /// for the first request to the origin.
+    pub fn digest_auth(self, username: &str, password: &str) -> Self {
+        self.auth(Auth::digest(username, password))
+    }
+
+    pub fn auth(mut self, auth: Auth) -> Self {
+        if let Ok(ref mut r) = self.request {
+            r.auth = Some(auth);
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<HttpRequest, HttpClientError> {
+        self.finish().1
+    }
+
+    pub async fn send(self) -> Result<Response, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        let (client, request) = self.finish();
+        client.execute(request?).await
+    }
+
+    /// Sends the request and returns once the status and the headers are received.
+    pub async fn send_streaming(self) -> Result<StreamingResponse<'a, S, E>, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        let (client, request) = self.finish();
+        client.execute_streaming(request?).await
+    }
+
+    /// Sends a `multipart/form-data` body, reading the streamed parts as the
+    /// request is written.
+    pub async fn send_multipart(self, form: Multipart<'_>) -> Result<Response, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        let (client, request) = self.finish();
+        client.execute_multipart(request?, form).await
+    }
+
+    fn finish(self) -> (&'a mut HttpClient<S, E, I>, Result<HttpRequest, HttpClientError>) {
+        let mut request = match self.request {
+            Ok(r) => r,
+            Err(e) => return (self.client, Err(e))
+        };
+
+        if !self.query.is_empty() {
+            let query: Vec<_> = self.query.iter()
+                .map(|(n, v)| format!("{}={}", encode_component(n), encode_component(v)))
+                .collect();
+
+            let path = request.url.path_or_root();
+            let separator = if path.contains('?') { "&" } else { "?" };
+            request.url.path = Some(format!("{}{}{}", path, separator, query.join("&")));
+        }
+
+        for (name, value) in self.client.default_headers() {
+            if request.header(name).is_none() {
+                request.headers.push((name.clone(), value.clone()));
+            }
+        }
+
+        (self.client, Ok(request))
+    }
+}
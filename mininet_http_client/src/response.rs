@@ -0,0 +1,222 @@
+use alloc::{string::{String, ToString}, vec, vec::Vec};
+use mininet_base::{req::HttpMethod, stack::{SystemEnvironment, TcpSocket}};
+use slog::{Logger, debug, info};
+
+use crate::{HttpClientError, headers::HeaderMap, redirect::Redirect, stream::BodyReader, timeout::{NoTimeouts, ReadTimer, Timeouts}};
+
+/// Size of the buffer used for each socket read.
+pub(crate) const READ_BUFFER_SIZE: usize = 512;
+
+/// Bounds on a response, so a misbehaving server can't exhaust the memory.
+#[derive(Debug, Copy, Clone)]
+pub struct ResponseLimits {
+    /// Headers of the response, and trailers of a chunked body.
+    pub max_headers: usize,
+    /// Size of the status line and the headers, and of the header of a
+    /// gzip body.
+    pub max_header_bytes: usize,
+    /// Size of a body that is read into memory, after decompression.
+    /// Streamed bodies aren't limited, apart from data sent after the end
+    /// of a compressed stream.
+    pub max_body_bytes: usize
+}
+
+impl Default for ResponseLimits {
+    fn default() -> Self {
+        ResponseLimits {
+            max_headers: 60,
+            max_header_bytes: 8 * 1024,
+            max_body_bytes: 1024 * 1024
+        }
+    }
+}
+
+/// A received response, whatever its status code.
+#[derive(Debug)]
+pub struct Response {
+    pub code: u16,
+    /// The reason phrase of the status line, can be empty.
+    pub reason: String,
+    /// Minor HTTP version, `HTTP/1.x`.
+    pub version: u8,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    /// Headers sent after a chunked body.
+    pub trailers: HeaderMap,
+    /// Redirects that were followed before this response.
+    pub redirects: Vec<Redirect>
+}
+
+#[derive(Debug)]
+pub enum ResponseError {
+    DeserializeError(serde_json::Error)
+}
+
+impl Response {
+    pub fn from_json<'a, T>(&'a self) -> Result<T, ResponseError>
+        where T: serde::Deserialize<'a>
+    {
+        serde_json::from_slice(self.body.as_slice()).map_err(ResponseError::DeserializeError)
+    }
+
+    /// First header with a matching name, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn is_success(&self) -> bool {
+        is_success(self.code)
+    }
+
+    /// Turns a status code outside of 2xx into an error.
+    pub fn error_for_status(self) -> Result<Self, HttpClientError> {
+        match self.is_success() {
+            true => Ok(self),
+            false => Err(HttpClientError::FailedStatusCode(self.code))
+        }
+    }
+}
+
+pub(crate) fn is_success(code: u16) -> bool {
+    (200..=299).contains(&code)
+}
+
+/// The status line and the headers of a response.
+#[derive(Debug)]
+pub(crate) struct ResponseHead {
+    pub code: u16,
+    pub reason: String,
+    /// Minor HTTP version, `HTTP/1.x`.
+    pub version: u8,
+    pub headers: HeaderMap
+}
+
+/// How the end of the response body is determined.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum BodyFraming {
+    Empty,
+    Length(usize),
+    Chunked,
+    Eof
+}
+
+impl ResponseHead {
+    pub fn framing(&self, method: HttpMethod) -> Result<BodyFraming, HttpClientError> {
+        if method == HttpMethod::Head || self.code == 204 || self.code == 304 || (100..200).contains(&self.code) {
+            return Ok(BodyFraming::Empty);
+        }
+
+        if let Some(te) = self.headers.get("Transfer-Encoding") {
+            if te.rsplit(',').next().map(|t| t.trim().eq_ignore_ascii_case("chunked")) == Some(true) {
+                return Ok(BodyFraming::Chunked);
+            }
+            return Ok(BodyFraming::Eof);
+        }
+
+        if let Some(len) = self.headers.get("Content-Length") {
+            let len = len.trim().parse().map_err(|_| HttpClientError::InvalidContentLength)?;
+            return Ok(BodyFraming::Length(len));
+        }
+
+        Ok(BodyFraming::Eof)
+    }
+
+    /// The server allows the connection to be used for another request.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.headers.get("Connection").unwrap_or("");
+        let has_token = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+
+        if has_token("close") {
+            return false;
+        }
+
+        self.version >= 1 || has_token("keep-alive")
+    }
+}
+
+/// Reads the status line and the headers. Any body data that was received
+/// together with the headers is left in `buf`.
+pub(crate) async fn read_head<T, E>(logger: &Logger, socket: &mut T, timer: &mut ReadTimer<E>, buf: &mut Vec<u8>, limits: &ResponseLimits) -> Result<ResponseHead, HttpClientError>
+    where T: TcpSocket, E: SystemEnvironment
+{
+    let mut read_buf = vec![0; READ_BUFFER_SIZE];
+
+    loop {
+        if !buf.is_empty() {
+            let mut headers_buffer = vec![httparse::EMPTY_HEADER; limits.max_headers];
+            let mut r = httparse::Response::new(&mut headers_buffer);
+
+            let status = match r.parse(buf) {
+                Err(httparse::Error::TooManyHeaders) => return Err(HttpClientError::TooManyHeaders),
+                status => status?
+            };
+            match status {
+                httparse::Status::Complete(n) if n > limits.max_header_bytes => return Err(HttpClientError::HeadersTooLarge),
+                httparse::Status::Partial if buf.len() > limits.max_header_bytes => return Err(HttpClientError::HeadersTooLarge),
+                _ => ()
+            }
+
+            if let httparse::Status::Complete(n) = status {
+                let code = r.code.ok_or(HttpClientError::MissingReponseCode)?;
+                let version = r.version.unwrap_or(1);
+                let reason = r.reason.unwrap_or("").to_string();
+
+                let headers: Vec<_> = r.headers.iter().filter_map(|h| {
+                    let v = alloc::str::from_utf8(h.value);
+
+                    match v {
+                        Ok(v) => Some((h.name.to_string(), v.to_string())),
+                        _ => None
+                    }
+                }).collect();
+
+                buf.drain(..n);
+
+                // interim responses are followed by the final one
+                if (100..200).contains(&code) && code != 101 {
+                    debug!(logger, "Skipping an interim response {}", code);
+                    continue;
+                }
+
+                info!(logger, "Response code: {}, headers: {:?}", code, headers);
+                return Ok(ResponseHead { code, reason, version, headers: headers.into() });
+            }
+        }
+
+        let n = timer.read(socket, &mut read_buf).await?;
+        if n == 0 {
+            return Err(HttpClientError::IncompleteResponse);
+        }
+        buf.extend_from_slice(&read_buf[..n]);
+    }
+}
+
+/// Reads a complete response, whatever its status code. The socket is only read until the end of the body.
+pub(crate) async fn read_response<T>(logger: &Logger, socket: &mut T, method: HttpMethod, limits: &ResponseLimits) -> Result<Response, HttpClientError>
+    where T: TcpSocket
+{
+    let mut timer = ReadTimer::new(NoTimeouts, &Timeouts::default());
+    let mut buf = vec![];
+    let head = read_head(logger, socket, &mut timer, &mut buf, limits).await?;
+
+    let framing = head.framing(method)?;
+    debug!(logger, "Response body framing: {:?}", framing);
+
+    let mut reader = BodyReader::new(framing, buf, limits);
+    let body = reader.read_to_end(socket, &mut timer, limits.max_body_bytes).await?;
+    let trailers = reader.take_trailers().into();
+    info!(logger, "Received body len: {}", body.len());
+    if let Ok(s) = alloc::str::from_utf8(&body) {
+        info!(logger, "Body as string: {}", s);
+    }
+
+    Ok(Response {
+        code: head.code,
+        reason: head.reason,
+        version: head.version,
+        headers: head.headers,
+        body,
+        trailers,
+        redirects: vec![]
+    })
+}
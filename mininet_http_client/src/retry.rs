@@ -0,0 +1,201 @@
+use core::time::Duration;
+
+use mininet_base::{req::HttpMethod, stack::TcpError};
+
+use crate::{HttpClientError, date::parse_http_date, headers::HeaderMap};
+
+/// What went wrong with an attempt.
+#[derive(Debug)]
+pub enum RetryCause<'a> {
+    Error(&'a HttpClientError),
+    /// A response was received, with this status code.
+    Status(u16)
+}
+
+/// Retries of failed requests, with an exponential backoff.
+///
+/// Each attempt has its own total timeout. The waits between the attempts
+/// are timed through the client's `SystemEnvironment`.
+#[derive(Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    /// Attempts after the first one, 0 disables retrying.
+    pub max_retries: usize,
+    /// The wait before the first retry, doubled for each following one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// A random part of up to half of the backoff is subtracted from each
+    /// wait, so that devices that failed together don't retry together.
+    pub jitter: bool,
+    /// The longest `Retry-After` that is waited for. With a longer one, the
+    /// response is returned.
+    pub max_retry_after: Duration,
+    /// Retry methods that are not idempotent, like `POST`.
+    pub retry_non_idempotent: bool,
+    /// Decides which failures are worth another attempt.
+    pub classifier: fn(&RetryCause) -> bool,
+    /// Current time in seconds since the Unix epoch, needed to honour a
+    /// `Retry-After` given as a date.
+    pub now: Option<fn() -> u64>
+}
+
+impl RetryPolicy {
+    /// Requests are attempted once.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    pub fn limited(max_retries: usize) -> Self {
+        RetryPolicy {
+            max_retries,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn with_classifier(mut self, classifier: fn(&RetryCause) -> bool) -> Self {
+        self.classifier = classifier;
+        self
+    }
+
+    /// Whether the request can be sent again at all.
+    pub fn allows(&self, method: HttpMethod, attempt: usize) -> bool {
+        attempt <= self.max_retries && (self.retry_non_idempotent || method.is_idempotent())
+    }
+
+    /// The wait before the retry number `attempt`, starting at 1.
+    pub fn backoff(&self, attempt: usize, jitter: &mut Jitter) -> Duration {
+        let shift = attempt.saturating_sub(1).min(31) as u32;
+        let backoff = self.initial_backoff.saturating_mul(1 << shift).min(self.max_backoff);
+
+        if !self.jitter {
+            return backoff;
+        }
+        let half = backoff.as_millis() as u64 / 2;
+        backoff - Duration::from_millis(jitter.next_u32() as u64 % (half + 1))
+    }
+
+    /// The wait requested by the server, `None` if there is no valid `Retry-After`.
+    pub fn retry_after(&self, headers: &HeaderMap) -> Option<Duration> {
+        let value = headers.get("Retry-After")?.trim();
+
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(Duration::from_secs(seconds));
+        }
+
+        let now = (self.now?)();
+        let date = parse_http_date(value)?;
+        Some(Duration::from_secs(date.saturating_sub(now)))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            max_retry_after: Duration::from_secs(120),
+            retry_non_idempotent: false,
+            classifier: is_retryable,
+            now: None
+        }
+    }
+}
+
+/// The default classifier. Network failures, timeouts, incomplete
+/// responses, `429 Too Many Requests` and 5xx replies are retried.
+pub fn is_retryable(cause: &RetryCause) -> bool {
+    match cause {
+        RetryCause::Error(e) => matches!(e,
+            HttpClientError::TcpError(TcpError::Closed | TcpError::Timeout | TcpError::Unknown) |
+            HttpClientError::IncompleteResponse |
+            HttpClientError::ConnectTimeout |
+            HttpClientError::FirstByteTimeout |
+            HttpClientError::ReadTimeout |
+            HttpClientError::Timeout
+        ),
+        RetryCause::Status(code) => *code == 429 || (500..=599).contains(code)
+    }
+}
+
+/// A small xorshift generator for the backoff jitter.
+#[derive(Debug, Clone)]
+pub struct Jitter(u32);
+
+impl Jitter {
+    pub fn new(seed: u32) -> Self {
+        Jitter(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec};
+
+    use super::*;
+
+    #[test]
+    fn retry_backoff() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::limited(10).with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+        };
+        let mut jitter = Jitter::new(1);
+        assert_eq!(Duration::from_millis(100), policy.backoff(1, &mut jitter));
+        assert_eq!(Duration::from_millis(400), policy.backoff(3, &mut jitter));
+        assert_eq!(Duration::from_secs(1), policy.backoff(8, &mut jitter));
+        assert_eq!(Duration::from_secs(1), policy.backoff(100, &mut jitter));
+
+        let policy = RetryPolicy { jitter: true, ..policy };
+        for attempt in 1..10 {
+            let full = RetryPolicy { jitter: false, ..policy }.backoff(attempt, &mut jitter);
+            let b = policy.backoff(attempt, &mut jitter);
+            assert!(b <= full && b >= full / 2);
+        }
+    }
+
+    #[test]
+    fn retry_methods_and_causes() {
+        let policy = RetryPolicy::limited(2);
+        assert!(policy.allows(HttpMethod::Get, 2));
+        assert!(!policy.allows(HttpMethod::Get, 3));
+        assert!(!policy.allows(HttpMethod::Post, 1));
+        assert!(RetryPolicy { retry_non_idempotent: true, ..policy }.allows(HttpMethod::Post, 1));
+
+        assert!(is_retryable(&RetryCause::Status(503)));
+        assert!(is_retryable(&RetryCause::Status(429)));
+        assert!(!is_retryable(&RetryCause::Status(404)));
+        assert!(is_retryable(&RetryCause::Error(&HttpClientError::TcpError(TcpError::Closed))));
+        assert!(!is_retryable(&RetryCause::Error(&HttpClientError::UrlParseError)));
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let headers = |v: &str| HeaderMap::from(vec![("Retry-After".to_string(), v.to_string())]);
+        let policy = RetryPolicy::default();
+        assert_eq!(Some(Duration::from_secs(120)), policy.retry_after(&headers("120")));
+        assert_eq!(None, policy.retry_after(&headers("Sun, 06 Nov 1994 08:49:37 GMT")));
+
+        let policy = RetryPolicy { now: Some(|| 784111770), ..policy };
+        assert_eq!(Some(Duration::from_secs(7)), policy.retry_after(&headers("Sun, 06 Nov 1994 08:49:37 GMT")));
+        assert_eq!(None, policy.retry_after(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,390 @@
+use core::time::Duration;
+
+use alloc::{collections::VecDeque, string::{String, ToString}, vec, vec::Vec};
+use futures::Stream;
+use mininet_base::stack::{SystemEnvironment, TcpStack};
+use slog::info;
+
+use crate::{HttpClientError, client::HttpClient, interceptor::{HttpInterceptor, Null}, retry::{RetryCause, is_retryable}, stream::DetachedBody};
+
+/// A message received from an event stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// The `event:` field, `message` when the server didn't set one.
+    pub event: String,
+    /// The `data:` lines, joined with newlines.
+    pub data: String,
+    /// The last `id:` received up to this event.
+    pub id: Option<String>
+}
+
+/// Longest event, and line, accepted by default.
+const MAX_EVENT_SIZE: usize = 64 * 1024;
+
+/// Incremental parser of the `text/event-stream` format.
+#[derive(Debug)]
+pub(crate) struct SseParser {
+    line: Vec<u8>,
+    /// The previous line ended with a CR, a following LF belongs to it.
+    after_cr: bool,
+    started: bool,
+    event: String,
+    data: String,
+    has_data: bool,
+    /// The `id:` of the event being received, it becomes the last event id
+    /// once the event is complete. `Some(None)` clears it.
+    pending_id: Option<Option<String>>,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+    max_event_size: usize
+}
+
+impl Default for SseParser {
+    fn default() -> Self {
+        SseParser {
+            line: vec![],
+            after_cr: false,
+            started: false,
+            event: String::new(),
+            data: String::new(),
+            has_data: false,
+            pending_id: None,
+            last_event_id: None,
+            retry: None,
+            max_event_size: MAX_EVENT_SIZE
+        }
+    }
+}
+
+impl SseParser {
+    /// Parses the input, complete events are appended to `events`. A line
+    /// or the data of an event longer than `max_event_size` is refused
+    /// with `EventTooLarge`.
+    pub fn feed(&mut self, mut input: &[u8], events: &mut VecDeque<Event>) -> Result<(), HttpClientError> {
+        if !self.started && !input.is_empty() {
+            self.started = true;
+            input = input.strip_prefix(b"\xEF\xBB\xBF".as_slice()).unwrap_or(input);
+        }
+
+        for &b in input {
+            match b {
+                b'\n' if self.after_cr => self.after_cr = false,
+                b'\r' | b'\n' => {
+                    self.after_cr = b == b'\r';
+                    let line = core::mem::take(&mut self.line);
+                    self.process_line(&String::from_utf8_lossy(&line), events)?;
+                },
+                _ => {
+                    self.after_cr = false;
+                    if self.line.len() >= self.max_event_size {
+                        return Err(HttpClientError::EventTooLarge);
+                    }
+                    self.line.push(b);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn process_line(&mut self, line: &str, events: &mut VecDeque<Event>) -> Result<(), HttpClientError> {
+        if line.is_empty() {
+            if let Some(id) = self.pending_id.take() {
+                self.last_event_id = id;
+            }
+            if self.has_data {
+                let mut data = core::mem::take(&mut self.data);
+                data.pop();
+                let event = core::mem::take(&mut self.event);
+                events.push_back(Event {
+                    event: if event.is_empty() { "message".to_string() } else { event },
+                    data,
+                    id: self.last_event_id.clone()
+                });
+            }
+            self.event.clear();
+            self.data.clear();
+            self.has_data = false;
+            return Ok(());
+        }
+
+        // a comment, often sent to keep the connection alive
+        if line.starts_with(':') {
+            return Ok(());
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                if self.data.len() + value.len() > self.max_event_size {
+                    return Err(HttpClientError::EventTooLarge);
+                }
+                self.data.push_str(value);
+                self.data.push('\n');
+                self.has_data = true;
+            },
+            "id" if !value.contains('\0') => {
+                self.pending_id = Some(if value.is_empty() { None } else { Some(value.to_string()) });
+            },
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(ms) = value.parse::<u64>() {
+                    self.retry = Some(Duration::from_millis(ms));
+                }
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Drops a partly received event, as its connection was lost. Its id
+    /// isn't kept, the event is received again after the reconnect.
+    pub fn reset_stream(&mut self) {
+        self.line.clear();
+        self.after_cr = false;
+        self.started = false;
+        self.event.clear();
+        self.data.clear();
+        self.has_data = false;
+        self.pending_id = None;
+    }
+}
+
+/// A Server-Sent Events subscription that reconnects by itself.
+///
+/// After a lost connection, the stream is requested again with the
+/// `Last-Event-ID` of the last event, once the retry delay passed. The delay
+/// is timed through the client's `SystemEnvironment`, the server can change
+/// it with the `retry:` field.
+pub struct EventSource<S, E, I = Null>
+    where S: TcpStack, E: SystemEnvironment
+{
+    client: HttpClient<S, E, I>,
+    url: String,
+    stream: Option<DetachedBody<S::TcpSocket, E>>,
+    parser: SseParser,
+    events: VecDeque<Event>,
+    retry: Duration,
+    buf: Vec<u8>,
+    closed: bool
+}
+
+impl<S, E, I> EventSource<S, E, I>
+    where S: TcpStack, E: SystemEnvironment, I: HttpInterceptor
+{
+    /// The client's read timeout also limits the time between two messages
+    /// of the stream, which can be used to detect dead connections when the
+    /// server sends regular comments.
+    pub fn new(client: HttpClient<S, E, I>, url: &str) -> Self {
+        EventSource {
+            client,
+            url: url.to_string(),
+            stream: None,
+            parser: SseParser::default(),
+            events: VecDeque::new(),
+            retry: Duration::from_secs(3),
+            buf: vec![0; 256],
+            closed: false
+        }
+    }
+
+    /// The wait before reconnecting, until the server sets one.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Longest event accepted, and longest line. A larger one ends the
+    /// stream with `EventTooLarge`, 64 KiB by default.
+    pub fn with_max_event_size(mut self, max: usize) -> Self {
+        self.parser.max_event_size = max;
+        self
+    }
+
+    /// Continues after the event with this id, like one saved before a restart.
+    pub fn with_last_event_id(mut self, id: &str) -> Self {
+        self.parser.last_event_id = Some(id.to_string());
+        self
+    }
+
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.parser.last_event_id.as_deref()
+    }
+
+    pub fn into_client(self) -> HttpClient<S, E, I> {
+        self.client
+    }
+
+    /// Waits for the next event. Returns `None` once the server ended the
+    /// subscription with a `204 No Content`.
+    ///
+    /// Lost connections, timeouts and the responses that are retried by
+    /// default, like 503, lead to a reconnect. Other status codes, a
+    /// response that isn't an event stream and an event over the size limit
+    /// are returned as errors.
+    pub async fn next_event(&mut self) -> Result<Option<Event>, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.closed {
+                return Ok(None);
+            }
+
+            let stream = match self.stream {
+                Some(ref mut stream) => stream,
+                None => {
+                    match self.connect().await {
+                        Ok(Some(stream)) => self.stream = Some(stream),
+                        Ok(None) => {
+                            self.closed = true;
+                            return Ok(None);
+                        },
+                        Err(ref e) if is_retryable(&RetryCause::Error(e)) => self.wait().await,
+                        Err(HttpClientError::FailedStatusCode(code)) if is_retryable(&RetryCause::Status(code)) => self.wait().await,
+                        Err(e) => return Err(e)
+                    }
+                    continue;
+                }
+            };
+
+            match stream.read(&mut self.buf).await {
+                Ok(n) if n > 0 => {
+                    if let Err(e) = self.parser.feed(&self.buf[..n], &mut self.events) {
+                        // the connection is dropped, the next call connects again
+                        self.stream = None;
+                        self.parser.reset_stream();
+                        return Err(e);
+                    }
+                },
+                r => {
+                    info!(self.client.logger(), "The event stream ended: {:?}", r);
+                    self.stream = None;
+                    self.parser.reset_stream();
+                    self.wait().await;
+                }
+            }
+        }
+    }
+
+    /// The events as a `Stream`, ending when the server ends the subscription.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Event, HttpClientError>>
+        where S: Send, E: Send + Sync
+    {
+        futures::stream::unfold(Some(self), |source| async move {
+            let mut source = source?;
+            match source.next_event().await {
+                Ok(Some(event)) => Some((Ok(event), Some(source))),
+                Ok(None) => None,
+                // the stream ends after an error
+                Err(e) => Some((Err(e), None))
+            }
+        })
+    }
+
+    async fn connect(&mut self) -> Result<Option<DetachedBody<S::TcpSocket, E>>, HttpClientError>
+        where S: Send, E: Send + Sync
+    {
+        let mut request = self.client.get(&self.url)
+            .header("Accept", "text/event-stream")
+            .header("Cache-Control", "no-cache");
+        if let Some(ref id) = self.parser.last_event_id {
+            request = request.header("Last-Event-ID", id);
+        }
+
+        let response = request.send_streaming().await?;
+        match response.code {
+            200 => {},
+            204 => return Ok(None),
+            code => return Err(HttpClientError::FailedStatusCode(code))
+        }
+        let event_stream = response.header("Content-Type")
+            .is_some_and(|t| t.trim().to_ascii_lowercase().starts_with("text/event-stream"));
+        if !event_stream {
+            return Err(HttpClientError::NotAnEventStream);
+        }
+
+        let stream = response.detach();
+        info!(self.client.logger(), "Subscribed to the event stream {}", self.url);
+        Ok(Some(stream))
+    }
+
+    async fn wait(&mut self) {
+        let retry = self.parser.retry.unwrap_or(self.retry);
+        info!(self.client.logger(), "Reconnecting to the event stream in {:?}", retry);
+        self.client.env().timeout(retry).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parts: &[&[u8]]) -> (Vec<Event>, SseParser) {
+        let mut parser = SseParser::default();
+        let mut events = VecDeque::new();
+        for part in parts {
+            parser.feed(part, &mut events).unwrap();
+        }
+        (events.into_iter().collect(), parser)
+    }
+
+    #[test]
+    fn sse_fields() {
+        let (events, parser) = parse(&[b"\xEF\xBB\xBFdata: first\n\n: keep-alive\n\nevent: config\ndata:{\"a\":1}\ndata:  two\nid: 7\nretry: 1500\n\ndata\n\n"]);
+        assert_eq!(vec![
+            Event { event: "message".to_string(), data: "first".to_string(), id: None },
+            Event { event: "config".to_string(), data: "{\"a\":1}\n two".to_string(), id: Some("7".to_string()) },
+            Event { event: "message".to_string(), data: "".to_string(), id: Some("7".to_string()) }
+        ], events);
+        assert_eq!(Some(Duration::from_millis(1500)), parser.retry);
+
+        // an event without data is not dispatched, an invalid retry is ignored
+        let (events, parser) = parse(&[b"event: ping\nretry: 10s\nid: 1\n\n"]);
+        assert!(events.is_empty());
+        assert_eq!((None, Some("1")), (parser.retry, parser.last_event_id.as_deref()));
+    }
+
+    #[test]
+    fn sse_line_endings() {
+        let (events, _) = parse(&[b"data: a\r", b"\ndata: b\r\r", b"data: c\r\n\r", b"\n", b"data: incomplete\n"]);
+        let data: Vec<&str> = events.iter().map(|e| e.data.as_str()).collect();
+        assert_eq!(vec!["a\nb", "c"], data);
+
+        let (events, mut parser) = parse(&[b"data: lost"]);
+        assert!(events.is_empty());
+        parser.reset_stream();
+        let mut events = VecDeque::new();
+        parser.feed(b"data: new\n\n", &mut events).unwrap();
+        assert_eq!("new", events[0].data);
+    }
+
+    #[test]
+    fn sse_id_on_dispatch() {
+        // the id of an event that was cut off isn't sent on the reconnect
+        let (events, mut parser) = parse(&[b"id: 1\ndata: a\n\nid: 2\ndata: b\n"]);
+        assert_eq!((1, Some("1")), (events.len(), parser.last_event_id.as_deref()));
+        parser.reset_stream();
+        let mut events = VecDeque::new();
+        parser.feed(b"data: c\n\n", &mut events).unwrap();
+        assert_eq!((Some("1"), Some("1")), (events[0].id.as_deref(), parser.last_event_id.as_deref()));
+
+        let (_, parser) = parse(&[b"id: 1\ndata: a\n\nid\ndata: b\n\n"]);
+        assert_eq!(None, parser.last_event_id);
+    }
+
+    #[test]
+    fn sse_event_size() {
+        let mut parser = SseParser { max_event_size: 10, ..Default::default() };
+        let mut events = VecDeque::new();
+        parser.feed(b"data: 1234\ndata: 5678\n\n", &mut events).unwrap();
+        assert_eq!("1234\n5678", events[0].data);
+        assert!(matches!(parser.feed(b"data: 1234\ndata: 567890\n", &mut events), Err(HttpClientError::EventTooLarge)));
+
+        let mut parser = SseParser { max_event_size: 10, ..Default::default() };
+        assert!(matches!(parser.feed(b"data: without an end", &mut events), Err(HttpClientError::EventTooLarge)));
+    }
+}
@@ -0,0 +1,411 @@
+use alloc::{string::String, vec, vec::Vec};
+use mininet_base::stack::{SystemEnvironment, TcpSocket, TcpStack};
+
+use crate::{HttpClientError, chunked::ChunkedDecoder, decode::ContentDecoder, client::{Exchange, Transport}, pool::ConnectionKey, redirect::Redirect, headers::HeaderMap, response::{BodyFraming, READ_BUFFER_SIZE, Response, ResponseLimits, is_success}, timeout::ReadTimer};
+
+/// Reads a response body from the socket, as described by its framing.
+pub(crate) struct BodyReader {
+    framing: BodyFraming,
+    remaining: usize,
+    decoder: ChunkedDecoder,
+    /// Raw data received from the socket, not yet decoded.
+    raw: Vec<u8>,
+    pos: usize,
+    done: bool
+}
+
+impl BodyReader {
+    /// `received` is the body data that arrived together with the headers.
+    pub fn new(framing: BodyFraming, received: Vec<u8>, limits: &ResponseLimits) -> Self {
+        BodyReader {
+            framing,
+            remaining: match framing { BodyFraming::Length(l) => l, _ => 0 },
+            decoder: ChunkedDecoder::new().with_max_trailers(limits.max_headers),
+            raw: received,
+            pos: 0,
+            done: framing == BodyFraming::Empty || framing == BodyFraming::Length(0)
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The connection can only be reused if the end of the body is known.
+    pub fn is_delimited(&self) -> bool {
+        self.framing != BodyFraming::Eof
+    }
+
+    pub fn take_trailers(&mut self) -> Vec<(String, String)> {
+        self.decoder.take_trailers()
+    }
+
+    /// Reads the body data received with the head, for a body that is
+    /// complete in memory. Returns 0 at the end of the data.
+    pub fn read_received(&mut self, out: &mut [u8]) -> usize {
+        let n = (self.raw.len() - self.pos).min(out.len()).min(self.remaining);
+        out[..n].copy_from_slice(&self.raw[self.pos..self.pos + n]);
+        self.pos += n;
+        self.remaining -= n;
+        self.done = self.remaining == 0;
+        n
+    }
+
+    /// Reads the next part of the body into `out`. Returns 0 at the end of the body.
+    pub async fn read<T, E>(&mut self, socket: &mut T, timer: &mut ReadTimer<E>, out: &mut [u8]) -> Result<usize, HttpClientError>
+        where T: TcpSocket, E: SystemEnvironment
+    {
+        if out.is_empty() {
+            return Ok(0);
+        }
+
+        while !self.done {
+            if self.pos == self.raw.len() {
+                self.raw.resize(READ_BUFFER_SIZE, 0);
+                self.pos = 0;
+                let n = timer.read(socket, &mut self.raw).await?;
+                self.raw.truncate(n);
+
+                if n == 0 {
+                    if self.framing == BodyFraming::Eof {
+                        self.done = true;
+                        break;
+                    }
+                    return Err(HttpClientError::IncompleteResponse);
+                }
+            }
+
+            let available = &self.raw[self.pos..];
+            let n = match self.framing {
+                BodyFraming::Empty => 0,
+                BodyFraming::Length(_) => {
+                    let n = available.len().min(out.len()).min(self.remaining);
+                    out[..n].copy_from_slice(&available[..n]);
+                    self.pos += n;
+                    self.remaining -= n;
+                    self.done = self.remaining == 0;
+                    n
+                },
+                BodyFraming::Eof => {
+                    let n = available.len().min(out.len());
+                    out[..n].copy_from_slice(&available[..n]);
+                    self.pos += n;
+                    n
+                },
+                BodyFraming::Chunked => {
+                    // limiting the input also limits the decoded data
+                    let input = &available[..available.len().min(out.len())];
+                    let (consumed, data) = self.decoder.decode(input)?;
+                    out[..data.len()].copy_from_slice(data);
+                    self.pos += consumed;
+                    self.done = self.decoder.is_done();
+                    data.len()
+                }
+            };
+
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// Reads the rest of the body into memory, up to `max_len` bytes.
+    pub async fn read_to_end<T, E>(&mut self, socket: &mut T, timer: &mut ReadTimer<E>, max_len: usize) -> Result<Vec<u8>, HttpClientError>
+        where T: TcpSocket, E: SystemEnvironment
+    {
+        if matches!(self.framing, BodyFraming::Length(l) if l > max_len) {
+            return Err(HttpClientError::BodyTooLarge);
+        }
+
+        let mut body = vec![];
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        loop {
+            let n = self.read(socket, timer, &mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            if body.len() + n > max_len {
+                return Err(HttpClientError::BodyTooLarge);
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+        Ok(body)
+    }
+
+    /// Reads the rest of the body without keeping it.
+    pub async fn skip_to_end<T, E>(&mut self, socket: &mut T, timer: &mut ReadTimer<E>) -> Result<(), HttpClientError>
+        where T: TcpSocket, E: SystemEnvironment
+    {
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        while self.read(socket, timer, &mut buf).await? > 0 {}
+        Ok(())
+    }
+}
+
+/// Decodes a compressed body as it is read.
+struct Decoding {
+    decoder: ContentDecoder,
+    /// Raw body data, not yet decoded.
+    encoded: Vec<u8>,
+    pos: usize,
+    /// Data dropped after the compressed stream, up to the `max_body_bytes`
+    /// of the response.
+    max_trailing: usize
+}
+
+impl Decoding {
+    async fn read<T, E>(&mut self, body: &mut BodyReader, socket: &mut T, timer: &mut ReadTimer<E>, out: &mut [u8]) -> Result<usize, HttpClientError>
+        where T: TcpSocket, E: SystemEnvironment
+    {
+        loop {
+            // the inflater can have output left even without new input
+            let (consumed, written) = self.decoder.decode(&self.encoded[self.pos..], out)?;
+            self.pos += consumed;
+            if written > 0 {
+                return Ok(written);
+            }
+
+            if self.decoder.is_done() {
+                // anything after the compressed stream is dropped, the connection can still be reused
+                let mut rest = [0; 64];
+                let mut dropped = 0;
+                while !body.is_done() {
+                    dropped += body.read(socket, timer, &mut rest).await?;
+                    if dropped > self.max_trailing {
+                        return Err(HttpClientError::InvalidContentEncoding);
+                    }
+                }
+                return Ok(0);
+            }
+
+            if self.pos < self.encoded.len() {
+                if consumed == 0 {
+                    return Err(HttpClientError::InvalidContentEncoding);
+                }
+                continue;
+            }
+
+            if body.is_done() {
+                return Err(HttpClientError::InvalidContentEncoding);
+            }
+            self.encoded.resize(READ_BUFFER_SIZE, 0);
+            let n = body.read(socket, timer, &mut self.encoded).await?;
+            self.encoded.truncate(n);
+            self.pos = 0;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.decoder.is_done()
+    }
+}
+
+/// A response whose status and headers were received, with the body still to be read.
+///
+/// Once the whole body is read, the connection goes back to the client's
+/// pool. Dropping the response earlier closes the connection.
+pub struct StreamingResponse<'a, S, E>
+    where S: TcpStack, E: SystemEnvironment
+{
+    pub code: u16,
+    /// The reason phrase of the status line, can be empty.
+    pub reason: String,
+    /// Minor HTTP version, `HTTP/1.x`.
+    pub version: u8,
+    pub headers: HeaderMap,
+    /// Redirects that were followed before this response.
+    pub redirects: Vec<Redirect>,
+    transport: &'a mut Transport<S, E>,
+    socket: Option<S::TcpSocket>,
+    key: ConnectionKey,
+    reusable: bool,
+    body: BodyReader,
+    timer: ReadTimer<E>,
+    decoding: Option<Decoding>,
+    trailers: HeaderMap
+}
+
+impl<'a, S, E> StreamingResponse<'a, S, E>
+    where S: TcpStack, E: SystemEnvironment
+{
+    pub(crate) fn new(transport: &'a mut Transport<S, E>, exchange: Exchange<S::TcpSocket, E>) -> Self {
+        let max_trailing = transport.limits().max_body_bytes;
+        let mut r = StreamingResponse {
+            code: exchange.head.code,
+            reason: exchange.head.reason,
+            version: exchange.head.version,
+            headers: exchange.head.headers,
+            redirects: exchange.redirects,
+            transport,
+            socket: Some(exchange.socket),
+            key: exchange.key,
+            reusable: exchange.reusable,
+            body: exchange.body,
+            timer: exchange.timer,
+            decoding: exchange.decoder.map(|decoder| Decoding { decoder, encoded: vec![], pos: 0, max_trailing }),
+            trailers: HeaderMap::new()
+        };
+        if r.is_complete() {
+            r.finish();
+        }
+        r
+    }
+
+    /// A response returned by an interceptor instead of the server's, its
+    /// body is read from memory.
+    pub(crate) fn buffered(transport: &'a mut Transport<S, E>, response: Response) -> Self {
+        let framing = BodyFraming::Length(response.body.len());
+        StreamingResponse {
+            code: response.code,
+            reason: response.reason,
+            version: response.version,
+            headers: response.headers,
+            redirects: response.redirects,
+            body: BodyReader::new(framing, response.body, transport.limits()),
+            timer: transport.read_timer(),
+            transport,
+            socket: None,
+            key: ConnectionKey::default(),
+            reusable: false,
+            decoding: None,
+            trailers: response.trailers
+        }
+    }
+
+    /// First header with a matching name, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Headers sent after a chunked body, available once the body was read.
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    pub fn is_success(&self) -> bool {
+        is_success(self.code)
+    }
+
+    /// Turns a status code outside of 2xx into an error, closing the connection.
+    pub fn error_for_status(self) -> Result<Self, HttpClientError> {
+        match self.is_success() {
+            true => Ok(self),
+            false => Err(HttpClientError::FailedStatusCode(self.code))
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.body.is_done() && self.decoding.as_ref().is_none_or(|d| d.is_done())
+    }
+
+    /// Reads the next part of the body into the buffer. Returns 0 at the end of the body.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, HttpClientError> {
+        let socket = match self.socket {
+            Some(ref mut s) => s,
+            // read, or given by an interceptor
+            None => return Ok(self.body.read_received(buf))
+        };
+
+        let n = match self.decoding {
+            Some(ref mut decoding) => decoding.read(&mut self.body, socket, &mut self.timer, buf).await?,
+            None => self.body.read(socket, &mut self.timer, buf).await?
+        };
+        if self.is_complete() {
+            self.finish();
+        }
+        Ok(n)
+    }
+
+    /// Pushes the body into the sink in parts of up to `chunk_size` bytes.
+    /// Returns the length of the body.
+    pub async fn write_to<F>(&mut self, chunk_size: usize, mut sink: F) -> Result<usize, HttpClientError>
+        where F: FnMut(&[u8]) -> Result<(), HttpClientError>
+    {
+        let mut buf = vec![0; chunk_size.max(1)];
+        let mut total = 0;
+        loop {
+            let n = self.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(total);
+            }
+            total += n;
+            sink(&buf[..n])?;
+        }
+    }
+
+    /// Reads the rest of the body into memory, within the client's `max_body_bytes`.
+    pub async fn into_response(mut self) -> Result<Response, HttpClientError> {
+        let max_len = self.transport.limits().max_body_bytes;
+        let mut body = vec![];
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        loop {
+            let n = self.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            if body.len() + n > max_len {
+                return Err(HttpClientError::BodyTooLarge);
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+        self.finish();
+
+        Ok(Response {
+            code: self.code,
+            reason: core::mem::take(&mut self.reason),
+            version: self.version,
+            headers: core::mem::take(&mut self.headers),
+            body,
+            trailers: core::mem::take(&mut self.trailers),
+            redirects: core::mem::take(&mut self.redirects)
+        })
+    }
+
+    /// Separates the body from the client, for a stream that outlives the
+    /// borrow. The connection isn't reused and closes with the body.
+    pub(crate) fn detach(self) -> DetachedBody<S::TcpSocket, E> {
+        DetachedBody {
+            socket: self.socket,
+            body: self.body,
+            timer: self.timer,
+            decoding: self.decoding
+        }
+    }
+
+    fn finish(&mut self) {
+        self.trailers.extend(self.body.take_trailers().into());
+
+        if let Some(socket) = self.socket.take() {
+            if self.reusable {
+                self.transport.checkin(self.key.clone(), socket);
+            }
+        }
+    }
+}
+
+/// The body of a streaming response, no longer tied to the client.
+pub(crate) struct DetachedBody<T, E> {
+    socket: Option<T>,
+    body: BodyReader,
+    timer: ReadTimer<E>,
+    decoding: Option<Decoding>
+}
+
+impl<T, E> DetachedBody<T, E>
+    where T: TcpSocket, E: SystemEnvironment
+{
+    /// Reads the next part of the body into the buffer. Returns 0 at the end of the body.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, HttpClientError> {
+        let socket = match self.socket {
+            Some(ref mut s) => s,
+            None => return Ok(self.body.read_received(buf))
+        };
+
+        match self.decoding {
+            Some(ref mut decoding) => decoding.read(&mut self.body, socket, &mut self.timer, buf).await,
+            None => self.body.read(socket, &mut self.timer, buf).await
+        }
+    }
+}
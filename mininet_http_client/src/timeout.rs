@@ -0,0 +1,81 @@
+use core::time::Duration;
+
+use mininet_base::stack::{SystemEnvironment, TcpSocket, with_timeout};
+
+use crate::HttpClientError;
+
+/// Time limits for the phases of a request. `None` waits indefinitely.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Timeouts {
+    /// Resolving the address and opening the connection.
+    pub connect: Option<Duration>,
+    /// From sending the request until the first byte of the response.
+    pub first_byte: Option<Duration>,
+    /// Maximum time between two reads of the response.
+    pub read: Option<Duration>,
+    /// The whole request. For streaming responses, until the headers are received.
+    pub total: Option<Duration>
+}
+
+impl Timeouts {
+    /// Fills the unset limits from `defaults`.
+    pub fn or(self, defaults: &Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(defaults.connect),
+            first_byte: self.first_byte.or(defaults.first_byte),
+            read: self.read.or(defaults.read),
+            total: self.total.or(defaults.total)
+        }
+    }
+}
+
+/// Socket reads limited by the first byte and the idle read timeouts.
+pub(crate) struct ReadTimer<E> {
+    env: E,
+    first_byte: Option<Duration>,
+    idle: Option<Duration>,
+    received: bool
+}
+
+impl<E> ReadTimer<E>
+    where E: SystemEnvironment
+{
+    pub fn new(env: E, timeouts: &Timeouts) -> Self {
+        ReadTimer {
+            env,
+            first_byte: timeouts.first_byte,
+            idle: timeouts.read,
+            received: false
+        }
+    }
+
+    pub async fn read<T>(&mut self, socket: &mut T, buf: &mut [u8]) -> Result<usize, HttpClientError>
+        where T: TcpSocket
+    {
+        let (timeout, error) = if self.received {
+            (self.idle, HttpClientError::ReadTimeout)
+        } else {
+            (self.first_byte, HttpClientError::FirstByteTimeout)
+        };
+
+        let n = match timeout {
+            Some(timeout) => with_timeout(&self.env, socket.read(buf), timeout).await.map_err(|_| error)??,
+            None => socket.read(buf).await?
+        };
+        self.received = true;
+
+        Ok(n)
+    }
+}
+
+/// An environment whose timeouts never expire, for reads without limits.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct NoTimeouts;
+
+impl SystemEnvironment for NoTimeouts {
+    type Timeout = futures::future::Pending<()>;
+
+    fn timeout(&self, _timeout: Duration) -> Self::Timeout {
+        futures::future::pending()
+    }
+}
@@ -0,0 +1,100 @@
+use alloc::{vec, vec::Vec};
+use mininet_base::chunked::{ChunkedDecoder, ChunkedError};
+
+use crate::{HttpServerError, RequestLimits};
+
+impl From<ChunkedError> for HttpServerError {
+    fn from(e: ChunkedError) -> Self {
+        match e {
+            ChunkedError::Invalid => Self::InvalidChunkedEncoding,
+            ChunkedError::ChunkTooLarge => Self::ChunkTooLarge,
+            ChunkedError::BodyTooLarge => Self::BodyTooLarge,
+            ChunkedError::TooManyTrailers => Self::TooManyHeaders
+        }
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` request body into memory.
+///
+/// The trailers are checked and dropped, they aren't added to the headers
+/// the handler trusts.
+#[derive(Debug)]
+pub(crate) struct ChunkedBody {
+    decoder: ChunkedDecoder,
+    body: Vec<u8>
+}
+
+impl ChunkedBody {
+    pub fn new(limits: &RequestLimits) -> Self {
+        ChunkedBody {
+            decoder: ChunkedDecoder::new()
+                .with_max_trailers(limits.max_headers)
+                .with_max_chunk_bytes(limits.max_chunk_bytes)
+                .with_max_body_bytes(limits.max_body_bytes),
+            body: vec![]
+        }
+    }
+
+    /// Decodes `input` up to the end of the body. Returns the number of
+    /// consumed bytes, what follows the body is left.
+    pub fn decode(&mut self, input: &[u8]) -> Result<usize, HttpServerError> {
+        Ok(self.decoder.decode_into(input, &mut self.body)?)
+    }
+
+    /// The terminating chunk and the trailers were received.
+    pub fn is_done(&self) -> bool {
+        self.decoder.is_done()
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_in_parts(encoded: &[u8], part: usize, limits: &RequestLimits) -> Result<Vec<u8>, HttpServerError> {
+        let mut decoder = ChunkedBody::new(limits);
+        let mut buf = vec![];
+        for data in encoded.chunks(part) {
+            buf.extend_from_slice(data);
+            let consumed = decoder.decode(&buf)?;
+            buf.drain(..consumed);
+            if decoder.is_done() {
+                return Ok(decoder.into_body());
+            }
+        }
+        Err(HttpServerError::ConnectionClosed)
+    }
+
+    #[test]
+    fn chunked_body() {
+        let limits = RequestLimits::default();
+        let encoded = b"5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: 1\r\n\r\nGET /next";
+        for part in [1, 3, 7, encoded.len()] {
+            assert_eq!(b"hello, world".to_vec(), decode_in_parts(encoded, part, &limits).unwrap());
+        }
+
+        // stops at the end of the body
+        let mut decoder = ChunkedBody::new(&limits);
+        let consumed = decoder.decode(encoded).unwrap();
+        assert!(decoder.is_done());
+        assert_eq!(b"GET /next", &encoded[consumed..]);
+
+        for invalid in [&b"+5\r\nhello\r\n0\r\n\r\n"[..], b"5\r\nhelloX\r\n0\r\n\r\n", b"z\r\n", b"0\r\nno colon\r\n\r\n", b"\r\n"] {
+            assert!(matches!(decode_in_parts(invalid, 4, &limits), Err(HttpServerError::InvalidChunkedEncoding)), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn chunked_body_limits() {
+        let limits = RequestLimits { max_chunk_bytes: 4, max_body_bytes: 6, ..Default::default() };
+        assert_eq!(b"abcdef".to_vec(), decode_in_parts(b"4\r\nabcd\r\n2\r\nef\r\n0\r\n\r\n", 5, &limits).unwrap());
+        assert!(matches!(decode_in_parts(b"5\r\nabcde\r\n0\r\n\r\n", 5, &limits), Err(HttpServerError::ChunkTooLarge)));
+        assert!(matches!(decode_in_parts(b"ffffffffffffffffff\r\n", 5, &limits), Err(HttpServerError::ChunkTooLarge)));
+        assert!(matches!(decode_in_parts(b"4\r\nabcd\r\n4\r\nefgh\r\n0\r\n\r\n", 5, &limits), Err(HttpServerError::BodyTooLarge)));
+        assert!(matches!(decode_in_parts(&[b'a'; 2000], 100, &limits), Err(HttpServerError::InvalidChunkedEncoding)));
+    }
+}
@@ -0,0 +1,188 @@
+use alloc::{format, vec, vec::Vec};
+use mininet_base::stack::{TcpError, TcpSocket};
+
+/// Longest response head that is buffered to add the framing headers.
+const MAX_HEAD_LENGTH: usize = 8 * 1024;
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// Collecting the status line and the headers written by the handler.
+    Head(Vec<u8>),
+    /// A status or a request without a body, the body written by the
+    /// handler is dropped.
+    NoBody,
+    /// Body bytes still expected after a `Content-Length`.
+    Length(usize),
+    /// The body is chunked by the server, the handler didn't give a length.
+    Chunked,
+    /// Written as it is, the connection is closed after the response.
+    Passthrough
+}
+
+/// Frames the response written by a handler, so the connection can be used
+/// for the next request.
+///
+/// The head is held back until it is complete. A response without a
+/// `Content-Length` is sent chunked to HTTP/1.1 clients, the connection is
+/// closed after it otherwise. The `Connection` header is added when needed.
+#[derive(Debug)]
+pub(crate) struct ResponseFraming {
+    state: State,
+    keep_alive: bool,
+    http_10: bool,
+    no_body: bool,
+    tunnel: bool
+}
+
+impl ResponseFraming {
+    /// `keep_alive` if the connection should be kept after this response.
+    pub fn new(keep_alive: bool, http_10: bool, method: Option<&str>) -> Self {
+        ResponseFraming {
+            state: State::Head(vec![]),
+            keep_alive,
+            http_10,
+            no_body: method == Some("HEAD"),
+            tunnel: method == Some("CONNECT")
+        }
+    }
+
+    pub async fn write<S>(&mut self, socket: &mut S, data: &[u8]) -> Result<(), TcpError>
+        where S: TcpSocket
+    {
+        match self.state {
+            State::Head(ref mut head) => {
+                head.extend_from_slice(data);
+                if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let mut head = core::mem::take(head);
+                    let body = head.split_off(end + 4);
+                    let head = self.frame_head(head);
+                    socket.send(&head).await?;
+                    if !body.is_empty() {
+                        self.write_body(socket, &body).await?;
+                    }
+                } else if head.len() > MAX_HEAD_LENGTH {
+                    let head = core::mem::take(head);
+                    self.close();
+                    socket.send(&head).await?;
+                }
+                Ok(())
+            },
+            _ => self.write_body(socket, data).await
+        }
+    }
+
+    /// Ends the response once the handler is done. Returns whether the
+    /// connection can be used for another request.
+    pub async fn finish<S>(&mut self, socket: &mut S) -> Result<bool, TcpError>
+        where S: TcpSocket
+    {
+        match self.state {
+            // written to the socket directly, or nothing at all
+            State::Head(ref mut head) => {
+                let head = core::mem::take(head);
+                self.close();
+                if !head.is_empty() {
+                    socket.send(&head).await?;
+                }
+            },
+            State::Chunked => {
+                socket.send(b"0\r\n\r\n").await?;
+            },
+            State::Length(remaining) if remaining > 0 => self.close(),
+            _ => ()
+        }
+
+        Ok(self.keep_alive)
+    }
+
+    async fn write_body<S>(&mut self, socket: &mut S, data: &[u8]) -> Result<(), TcpError>
+        where S: TcpSocket
+    {
+        match self.state {
+            State::Chunked if !data.is_empty() => {
+                let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+                chunk.extend_from_slice(data);
+                chunk.extend_from_slice(b"\r\n");
+                socket.send(&chunk).await?;
+                return Ok(());
+            },
+            State::Length(ref mut remaining) => {
+                if data.len() > *remaining {
+                    // more than announced, the client would take it as the next response
+                    self.keep_alive = false;
+                }
+                *remaining = remaining.saturating_sub(data.len());
+            },
+            // the handler wrote the body of a HEAD request or a 204 anyway
+            State::NoBody => return Ok(()),
+            _ => ()
+        }
+
+        socket.send(data).await?;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.keep_alive = false;
+        self.state = State::Passthrough;
+    }
+
+    /// Picks the framing of the body and adds the headers it needs.
+    fn frame_head(&mut self, mut head: Vec<u8>) -> Vec<u8> {
+        let mut headers_buffer = [httparse::EMPTY_HEADER; 64];
+        let mut r = httparse::Response::new(&mut headers_buffer);
+        let code = match r.parse(&head) {
+            Ok(httparse::Status::Complete(_)) => r.code.unwrap_or(0),
+            _ => 0
+        };
+        let header = |name: &str| r.headers.iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .and_then(|h| core::str::from_utf8(h.value).ok());
+
+        let connection = header("Connection").unwrap_or("");
+        let close_requested = connection.split(',').any(|t| t.trim().eq_ignore_ascii_case("close"));
+        let has_connection = !connection.is_empty();
+        let content_length = header("Content-Length").and_then(|l| l.trim().parse::<usize>().ok());
+        let transfer_encoding = header("Transfer-Encoding").is_some();
+
+        if close_requested {
+            self.keep_alive = false;
+        }
+
+        self.state = if code < 200 || self.tunnel && (200..300).contains(&code) {
+            // switched protocols or a tunnel, the connection is no longer HTTP
+            self.keep_alive = false;
+            State::Passthrough
+        } else if self.no_body || code == 204 || code == 304 {
+            State::NoBody
+        } else if transfer_encoding {
+            // the handler chunks the body itself, its end isn't followed
+            self.keep_alive = false;
+            State::Passthrough
+        } else if let Some(len) = content_length {
+            State::Length(len)
+        } else if self.keep_alive && !self.http_10 {
+            State::Chunked
+        } else {
+            self.keep_alive = false;
+            State::Passthrough
+        };
+
+        let mut extra = Vec::new();
+        if self.state == State::Chunked {
+            extra.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+        }
+        if !has_connection && code >= 200 {
+            match (self.keep_alive, self.http_10) {
+                (false, _) => extra.extend_from_slice(b"Connection: close\r\n"),
+                (true, true) => extra.extend_from_slice(b"Connection: keep-alive\r\n"),
+                (true, false) => ()
+            }
+        }
+        if !extra.is_empty() {
+            let end = head.len() - 2;
+            head.splice(end..end, extra);
+        }
+        head
+    }
+}
@@ -0,0 +1,102 @@
+use core::{sync::atomic::{AtomicBool, AtomicUsize, Ordering}, task::Poll};
+
+use alloc::{sync::Arc, vec::Vec};
+use futures::{Future, future::{Either, poll_fn, select}, task::AtomicWaker};
+
+/// Wakes the server when a kept connection starts waiting for its next request.
+#[derive(Default)]
+struct IdleSignal {
+    server: AtomicWaker,
+    /// Orders the idle connections, the oldest is closed first.
+    sequence: AtomicUsize
+}
+
+/// A connection, as the server sees it.
+pub(crate) struct IdleState {
+    signal: Arc<IdleSignal>,
+    /// Since when the connection waits for a request, 0 while it is busy.
+    since: AtomicUsize,
+    close: AtomicBool,
+    waker: AtomicWaker
+}
+
+impl IdleState {
+    /// Waits for the next request with `read`. Returns `None` if the server
+    /// closes the connection to make room for a new one.
+    pub async fn wait<F>(&self, read: F) -> Option<F::Output>
+        where F: Future + Unpin
+    {
+        let closed = poll_fn(|cx| {
+            self.waker.register(cx.waker());
+            match self.close.load(Ordering::Acquire) {
+                true => Poll::Ready(()),
+                false => Poll::Pending
+            }
+        });
+
+        self.since.store(self.signal.sequence.fetch_add(1, Ordering::Relaxed) + 1, Ordering::Release);
+        self.signal.server.wake();
+        let read = select(read, closed).await;
+        self.since.store(0, Ordering::Release);
+
+        match read {
+            Either::Left((read, _)) => Some(read),
+            Either::Right(_) => None
+        }
+    }
+
+    fn is_idle(self: &Arc<Self>) -> bool {
+        // the state of a finished connection is only held by the server
+        Arc::strong_count(self) > 1 && self.since.load(Ordering::Acquire) != 0 && !self.close.load(Ordering::Acquire)
+    }
+}
+
+/// The connections of the server, to close the oldest one waiting for its
+/// next request when the server is at capacity.
+#[derive(Default)]
+pub(crate) struct IdleConnections {
+    signal: Arc<IdleSignal>,
+    connections: Vec<Arc<IdleState>>
+}
+
+impl IdleConnections {
+    /// The state of a new connection.
+    pub fn add(&mut self) -> Arc<IdleState> {
+        self.connections.retain(|c| Arc::strong_count(c) > 1);
+        let state = Arc::new(IdleState {
+            signal: self.signal.clone(),
+            since: AtomicUsize::new(0),
+            close: AtomicBool::new(false),
+            waker: AtomicWaker::new()
+        });
+        self.connections.push(state.clone());
+        state
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.connections.iter().any(|c| c.is_idle())
+    }
+
+    /// Closes the oldest idle connection. Returns `false` if there is none.
+    pub fn close_oldest(&self) -> bool {
+        match self.connections.iter().filter(|c| c.is_idle()).min_by_key(|c| c.since.load(Ordering::Acquire)) {
+            Some(oldest) => {
+                oldest.close.store(true, Ordering::Release);
+                oldest.waker.wake();
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Completes once a connection is idle.
+    pub fn any(&self) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| {
+            self.signal.server.register(cx.waker());
+            match self.is_empty() {
+                true => Poll::Pending,
+                false => Poll::Ready(())
+            }
+        })
+    }
+}
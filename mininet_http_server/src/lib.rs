@@ -2,23 +2,72 @@
 
 extern crate alloc;
 
-use core::time::Duration;
+mod chunked;
+mod framing;
+mod idle;
+
+use core::{task::Poll, time::Duration};
 
 use alloc::{
     boxed::Box,
-    string::{String, ToString},
+    sync::Arc,
+    format,
+    string::ToString,
     vec,
     vec::Vec,
 };
 use async_trait::async_trait;
-use futures::Future;
+use futures::{Future, StreamExt, channel::oneshot, future::{Either, poll_fn, select}, stream::FuturesUnordered};
 use mininet_base::{req::{HttpServerHeader, HttpServerRequest}, resp::HttpResponseWriter, stack::{SystemEnvironment, TcpError, TcpListen, TcpSocket, with_timeout}};
-use slog::{Logger, debug, error, info, o};
+use slog::{Logger, debug, error, info, o, warn};
+
+use crate::{chunked::ChunkedBody, framing::ResponseFraming, idle::{IdleConnections, IdleState}};
 
 #[derive(Debug, Copy, Clone)]
 pub enum HttpServerError {
     Unknown,
     TcpError(TcpError),
+    /// The client closed the connection before a complete request.
+    ConnectionClosed,
+    /// The request line or the headers are malformed.
+    InvalidRequest,
+    InvalidContentLength,
+    /// Several different `Content-Length`s, or one with a `Transfer-Encoding`.
+    /// Proxies could disagree on where the request ends.
+    ConflictingLength,
+    /// The `Transfer-Encoding` doesn't end with `chunked`, the end of the body is unknown.
+    InvalidTransferEncoding,
+    /// A transfer coding other than `chunked`, like `gzip`, that can't be decoded.
+    UnsupportedTransferEncoding,
+    InvalidChunkedEncoding,
+    /// A chunk is longer than `RequestLimits::max_chunk_bytes`.
+    ChunkTooLarge,
+    /// The request has more headers than `RequestLimits::max_headers`.
+    TooManyHeaders,
+    /// The request head is longer than `RequestLimits::max_header_bytes`.
+    HeadersTooLarge,
+    /// The body is longer than `RequestLimits::max_body_bytes`.
+    BodyTooLarge,
+    /// Not an HTTP/1.x request.
+    UnsupportedVersion,
+    /// A `POST`, `PUT` or `PATCH` without a `Content-Length` or a chunked body.
+    LengthRequired,
+}
+
+impl HttpServerError {
+    /// The status the client is answered with, `None` if the connection is just closed.
+    pub fn status(&self) -> Option<(u16, &'static str)> {
+        match self {
+            HttpServerError::Unknown | HttpServerError::TcpError(_) | HttpServerError::ConnectionClosed => None,
+            HttpServerError::InvalidRequest | HttpServerError::InvalidContentLength | HttpServerError::ConflictingLength
+                | HttpServerError::InvalidTransferEncoding | HttpServerError::InvalidChunkedEncoding => Some((400, "Bad Request")),
+            HttpServerError::LengthRequired => Some((411, "Length Required")),
+            HttpServerError::BodyTooLarge | HttpServerError::ChunkTooLarge => Some((413, "Payload Too Large")),
+            HttpServerError::TooManyHeaders | HttpServerError::HeadersTooLarge => Some((431, "Request Header Fields Too Large")),
+            HttpServerError::UnsupportedTransferEncoding => Some((501, "Not Implemented")),
+            HttpServerError::UnsupportedVersion => Some((505, "HTTP Version Not Supported")),
+        }
+    }
 }
 
 impl From<TcpError> for HttpServerError {
@@ -27,7 +76,91 @@ impl From<TcpError> for HttpServerError {
     }
 }
 
-pub async fn http_server<L, H, Fut, E>(logger: &Logger, env: E, mut listen: L, handler: H, request_timeout: Option<Duration>)
+/// What happens to the connections accepted over `HttpServerConfig::max_connections`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WhenBusy {
+    /// Stop accepting, new connections wait in the listen backlog of the stack.
+    Backlog,
+    /// Accept and answer with `503 Service Unavailable`.
+    Reject
+}
+
+/// Bounds on a request, so a misbehaving client can't exhaust the memory.
+#[derive(Debug, Copy, Clone)]
+pub struct RequestLimits {
+    pub max_headers: usize,
+    /// Size of the request line and the headers.
+    pub max_header_bytes: usize,
+    /// Size of the body, also when it is chunked.
+    pub max_body_bytes: usize,
+    /// Size of a single chunk of a chunked body.
+    pub max_chunk_bytes: usize
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_headers: 60,
+            max_header_bytes: 8 * 1024,
+            max_body_bytes: 64 * 1024,
+            max_chunk_bytes: 16 * 1024
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HttpServerConfig {
+    /// Connections that are handled at the same time.
+    pub max_connections: usize,
+    pub when_busy: WhenBusy,
+    /// Limits the handling of a request, from reading it to the end of the handler.
+    pub request_timeout: Option<Duration>,
+    /// How long a kept connection waits for the next request.
+    pub idle_timeout: Option<Duration>,
+    /// Requests answered on a connection before it is closed, 1 disables keep-alive.
+    pub max_requests_per_connection: usize,
+    pub limits: RequestLimits
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        HttpServerConfig {
+            max_connections: 4,
+            when_busy: WhenBusy::Backlog,
+            request_timeout: Some(Duration::from_secs(30)),
+            idle_timeout: Some(Duration::from_secs(5)),
+            max_requests_per_connection: 100,
+            limits: RequestLimits::default()
+        }
+    }
+}
+
+/// Serves with the default configuration, except that the size of the body
+/// isn't limited. Use `http_server_with_config` to bound it.
+pub async fn http_server<L, H, Fut, E>(logger: &Logger, env: E, listen: L, handler: H, request_timeout: Option<Duration>)
+where
+    H: Fn(HttpContext<L::TcpSocket>) -> Fut,
+    Fut: Future<Output = ()>,
+    L: TcpListen,
+    E: SystemEnvironment
+{
+    let config = HttpServerConfig {
+        request_timeout,
+        limits: RequestLimits { max_body_bytes: usize::MAX, ..Default::default() },
+        ..Default::default()
+    };
+    http_server_with_config(logger, env, listen, handler, config).await
+}
+
+/// Serves the connections concurrently, up to `max_connections`. The
+/// connections are driven by this future, no executor is needed to spawn them.
+/// At capacity, the oldest connection waiting for its next request is closed
+/// to make room for a new one.
+///
+/// A connection is kept for further requests as HTTP/1.1 and its `Connection`
+/// header allow, pipelined requests are answered in order. A response without
+/// a `Content-Length` is sent chunked, so the connection can stay open.
+pub async fn http_server_with_config<L, H, Fut, E>(logger: &Logger, env: E, mut listen: L, handler: H, config: HttpServerConfig)
 where
     H: Fn(HttpContext<L::TcpSocket>) -> Fut,
     Fut: Future<Output = ()>,
@@ -35,49 +168,46 @@ where
     E: SystemEnvironment
 {
     let logger = logger.new(o!("ctx" => "http_server"));
+    let max_connections = config.max_connections.max(1);
+    let mut idle = IdleConnections::default();
+    let mut connections = FuturesUnordered::new();
+    // the connections answered with a 503, at most as many as are served
+    let mut rejections = FuturesUnordered::new();
 
     let mut id: usize = 1;
     info!(logger, "Http server listening");
     loop {
-        match listen.accept().await {
-            Ok((mut socket, addr)) => {
-                info!(logger, "Accepted a socket from {:?}", addr);
+        let accepted = if connections.is_empty() && rejections.is_empty() {
+            listen.accept().await
+        } else if connections.len() < max_connections || config.when_busy == WhenBusy::Reject || !idle.is_empty() {
+            // a finished connection makes room, the pending accept is dropped and started again
+            match select(listen.accept(), next_finished(&mut connections, &mut rejections)).await {
+                Either::Left((accepted, _)) => accepted,
+                Either::Right(_) => continue
+            }
+        } else {
+            debug!(logger, "{} connections in flight, waiting for one to finish or to become idle.", connections.len());
+            select(next_finished(&mut connections, &mut rejections), idle.any()).await;
+            continue;
+        };
 
-                let logger = logger.new(o!("request_id" => id));
-                id += 1;
+        match accepted {
+            Ok((socket, addr)) => {
+                info!(logger, "Accepted a socket from {:?}", addr);
 
-                let handle_request = async {
-                    let http_parse = parse(&logger, &mut socket);
-
-                    match http_parse.await {
-                        Ok(req) => {
-                            info!(logger, "HTTP request: {:#?}", req);
-
-                            let ctx = HttpContext {
-                                logger: logger.clone(),
-                                request: req,
-                                socket,
-                            };
-                            handler(ctx).await;
-
-                            info!(logger, "Request handler finished.");
-                        }
-                        Err(e) => {
-                            error!(logger, "Failed to parse the requst: {:?}", e);
-                        }
-                    }
-                };
-
-                if let Some(t) = request_timeout {
-                    match with_timeout(&env, handle_request, t).await {
-                        Ok(_) => (),
-                        Err(_) => {
-                            error!(logger, "The incoming request timed out after {} seconds.", t.as_secs());
-                        }
+                if connections.len() >= max_connections && !idle.close_oldest() {
+                    if rejections.len() >= max_connections {
+                        warn!(logger, "Too many connections, closing {:?}", addr);
+                    } else {
+                        warn!(logger, "Too many connections, rejecting {:?}", addr);
+                        rejections.push(reject_busy(&env, socket));
                     }
-                } else {
-                    handle_request.await;
+                    continue;
                 }
+
+                let logger = logger.new(o!("request_id" => id));
+                id += 1;
+                connections.push(handle_connection(logger, &env, &handler, idle.add(), socket, config));
             }
             Err(_) => {
                 error!(logger, "Listen socket stopped, shutting down.");
@@ -85,116 +215,385 @@ where
             }
         }
     }
+
+    // the connections in flight are finished
+    while connections.next().await.is_some() {}
+    while rejections.next().await.is_some() {}
 }
 
+/// Completes when one of the connections or of the rejections is finished.
+fn next_finished<'a, C, R>(connections: &'a mut FuturesUnordered<C>, rejections: &'a mut FuturesUnordered<R>) -> impl Future<Output = ()> + 'a
+where
+    C: Future<Output = ()>,
+    R: Future<Output = ()>
+{
+    poll_fn(move |cx| {
+        let connection = !connections.is_empty() && connections.poll_next_unpin(cx).is_ready();
+        let rejection = !rejections.is_empty() && rejections.poll_next_unpin(cx).is_ready();
+        match connection || rejection {
+            true => Poll::Ready(()),
+            false => Poll::Pending
+        }
+    })
+}
 
-pub async fn parse<S>(logger: &Logger, socket: &mut S) -> Result<HttpServerRequest, HttpServerError>
+async fn handle_connection<S, H, Fut, E>(logger: Logger, env: &E, handler: &H, idle: Arc<IdleState>, mut socket: S, config: HttpServerConfig)
 where
     S: TcpSocket,
+    H: Fn(HttpContext<S>) -> Fut,
+    Fut: Future<Output = ()>,
+    E: SystemEnvironment
 {
-    let mut recv_header = vec![];
+    // pipelined data read after the previous request
+    let mut buf = vec![];
+    let mut served = 0;
+
     loop {
-        let mut buf = [0; 64];        
-        debug!(logger, "Reading header data");
-        let incomplete = match socket.read(&mut buf).await {
-            Ok(d) if d == 0 => {
-                error!(logger, "Socket closed message received?");
-                return Err(HttpServerError::Unknown);
-            }
-            Ok(b) => {
-                recv_header.extend(&buf[0..b]);
-                debug!(logger, "Received {} bytes of header data", b);
-            
-                b < buf.len()
+        if served > 0 && buf.is_empty() {
+            let mut first = [0; 64];
+            // `None` when the server closes it to make room for a new connection
+            let read = idle.wait(socket.read(&mut first));
+            let read = match config.idle_timeout {
+                Some(t) => match with_timeout(env, read, t).await {
+                    Ok(r) => r,
+                    Err(_) => {
+                        debug!(logger, "The connection was idle for {} seconds, closing.", t.as_secs());
+                        break;
+                    }
+                },
+                None => read.await
+            };
+            match read {
+                None => {
+                    debug!(logger, "Closing the idle connection to make room for a new one.");
+                    break;
+                },
+                Some(Ok(n)) if n > 0 => buf.extend_from_slice(&first[..n]),
+                _ => {
+                    debug!(logger, "The connection was closed by the client.");
+                    break;
+                }
             }
-            Err(e) => {
-                error!(logger, "Network error during parsing: {:?}", e);
-                return Err(HttpServerError::Unknown);
+        }
+
+        served += 1;
+        let last = served >= config.max_requests_per_connection;
+        let handle_request = handle_request(&logger, env, handler, socket, &mut buf, last, &config.limits);
+
+        let kept = if let Some(t) = config.request_timeout {
+            match with_timeout(env, handle_request, t).await {
+                Ok(kept) => kept,
+                Err(_) => {
+                    error!(logger, "The incoming request timed out after {} seconds.", t.as_secs());
+                    None
+                }
             }
+        } else {
+            handle_request.await
         };
 
-        if incomplete {
-            debug!(logger, "Incomplete last read?");
+        match kept {
+            Some(s) => socket = s,
+            None => break
         }
+    }
+}
 
-        let mut headers_buffer = [httparse::EMPTY_HEADER; 60];
-
-        let mut r = httparse::Request::new(&mut headers_buffer);
-        let n = match r.parse(recv_header.as_slice()) {
-            Ok(httparse::Status::Complete(size)) => size,
-            Ok(httparse::Status::Partial) => {
-                debug!(logger, "Partial headers, getting more data");
-                continue;
-            }
-            Err(e) => {
-                error!(logger, "HTTP Parser error: {:?}", e);
-                return Err(HttpServerError::Unknown);
+/// Reads a request from the connection and runs the handler. Returns the
+/// socket if the connection can be used for the next request.
+async fn handle_request<S, H, Fut, E>(logger: &Logger, env: &E, handler: &H, mut socket: S, buf: &mut Vec<u8>, last: bool, limits: &RequestLimits) -> Option<S>
+where
+    S: TcpSocket,
+    H: Fn(HttpContext<S>) -> Fut,
+    Fut: Future<Output = ()>,
+    E: SystemEnvironment
+{
+    let (req, version) = match read_request(logger, &mut socket, buf, limits).await {
+        Ok(r) => r,
+        Err(HttpServerError::ConnectionClosed) => {
+            debug!(logger, "The connection was closed without a request.");
+            return None;
+        }
+        Err(e) => {
+            error!(logger, "Failed to parse the requst: {:?}", e);
+            if let Some((code, reason)) = e.status() {
+                reject(env, &mut socket, code, reason).await;
             }
-        };
+            return None;
+        }
+    };
+    info!(logger, "HTTP request: {:#?}", req);
+
+    let connection = req.headers.iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|h| h.value.split(','))
+        .map(|t| t.trim())
+        .fold((false, false), |(close, keep), t| (close || t.eq_ignore_ascii_case("close"), keep || t.eq_ignore_ascii_case("keep-alive")));
+    let keep_alive = !last && match version {
+        0 => connection.1 && !connection.0,
+        _ => !connection.0
+    };
+
+    let (returned, mut recovered) = oneshot::channel();
+    let ctx = HttpContext {
+        logger: logger.clone(),
+        response: Box::new(ResponseFraming::new(keep_alive, version == 0, req.method.as_deref())),
+        request: req,
+        socket: Some(socket),
+        returned: Some(returned)
+    };
+    handler(ctx).await;
+
+    info!(logger, "Request handler finished.");
+
+    // the context is gone with the handler, unless it was kept somewhere
+    let (mut socket, mut response) = match recovered.try_recv() {
+        Ok(Some(r)) => r,
+        _ => return None
+    };
+    match response.finish(&mut socket).await {
+        Ok(true) => Some(socket),
+        _ => None
+    }
+}
+
+/// Answers a request that can't be handled and closes the connection. What
+/// the client still sends is read for a moment, closing with unread data
+/// would reset the connection before the client gets the reply.
+async fn reject<S, E>(env: &E, socket: &mut S, code: u16, reason: &str)
+where
+    S: TcpSocket,
+    E: SystemEnvironment
+{
+    let reply = format!("HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", code, reason);
+    if socket.send(reply.as_bytes()).await.is_ok() {
+        drain(env, socket, Duration::from_millis(500)).await;
+    }
+}
+
+/// Answers a connection over `max_connections` with a `503`, like `reject`
+/// but with a shorter drain, the server is busy.
+async fn reject_busy<S, E>(env: &E, mut socket: S)
+where
+    S: TcpSocket,
+    E: SystemEnvironment
+{
+    if socket.send(b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await.is_ok() {
+        drain(env, &mut socket, Duration::from_millis(100)).await;
+    }
+}
 
-        let method = r.method.map(|m| m.to_string());
-        let path = r.path.map(|p| p.to_string());
+/// Reads what the client still sends for a moment, before the connection is closed.
+async fn drain<S, E>(env: &E, socket: &mut S, duration: Duration)
+where
+    S: TcpSocket,
+    E: SystemEnvironment
+{
+    let discard = async {
+        let mut buf = [0; 64];
+        while let Ok(n) = socket.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+        }
+    };
+    let _ = with_timeout(env, discard, duration).await;
+}
 
-        let body_size = headers_buffer.iter()
-            .filter(|h| h.name == "Content-Length")
-            .flat_map(|h| core::str::from_utf8(h.value))
-            .flat_map(|v| v.parse::<usize>())
-            .next();
+pub async fn parse<S>(logger: &Logger, socket: &mut S) -> Result<HttpServerRequest, HttpServerError>
+where
+    S: TcpSocket,
+{
+    read_request(logger, socket, &mut vec![], &RequestLimits::default()).await.map(|(req, _)| req)
+}
 
-        let mut body = recv_header[n..].to_vec();
+/// Reads the next request from the data in `buf` and the socket. Anything
+/// after the request, like a pipelined one, is left in `buf`. Returns the
+/// request and the minor HTTP version.
+async fn read_request<S>(logger: &Logger, socket: &mut S, buf: &mut Vec<u8>, limits: &RequestLimits) -> Result<(HttpServerRequest, u8), HttpServerError>
+where
+    S: TcpSocket,
+{
+    let head = loop {
+        if let Some(head) = parse_head(logger, buf, limits)? {
+            break head;
+        }
+        if buf.len() > limits.max_header_bytes {
+            return Err(HttpServerError::HeadersTooLarge);
+        }
+        debug!(logger, "Reading header data");
+        read_more(logger, socket, buf).await?;
+    };
+    buf.drain(..head.len);
 
-        // read in the remaining body, if any
-        if let Some(body_size) = body_size {
+    let body = match head.body {
+        RequestBody::Length(0) => vec![],
+        RequestBody::Length(body_size) => {
             debug!(logger, "Request body size: {}", body_size);
-            let mut remaining = body_size - body.len(); // of by one?
+            if body_size > limits.max_body_bytes {
+                return Err(HttpServerError::BodyTooLarge);
+            }
+            if head.expect_continue && buf.len() < body_size {
+                socket.send(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+            }
+
+            // read in the remaining body, if any
+            while buf.len() < body_size {
+                read_more(logger, socket, buf).await?;
+            }
+            buf.drain(..body_size).collect()
+        },
+        RequestBody::Chunked => {
+            debug!(logger, "Chunked request body");
+            if head.expect_continue && buf.is_empty() {
+                socket.send(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+            }
 
+            let mut chunked = ChunkedBody::new(limits);
             loop {
-                if remaining == 0 {
-                    debug!(logger, "Whole body received.");
+                let consumed = chunked.decode(buf)?;
+                buf.drain(..consumed);
+                if chunked.is_done() {
                     break;
                 }
-                
-                let b = remaining.min(buf.len());
-                debug!(logger, "Reading {} bytes of additional body data", b);
-                match socket.read(&mut buf[0..b]).await {
-                    Ok(d) if d == 0 => {
-                        error!(logger, "Socket closed message received?");
-                        return Err(HttpServerError::Unknown);
-                    }
-                    Ok(b) => {
-                        body.extend(&buf[0..b]);
-                        remaining -= b;
-                    }
-                    Err(e) => {
-                        error!(logger, "Network error during body receive: {:?}", e);
-                        return Err(HttpServerError::Unknown);
-                    }
-                }
+                read_more(logger, socket, buf).await?;
             }
-        }        
-
-        let req = HttpServerRequest {
-            method,
-            path,
-            body,
-            headers: headers_buffer
-                .iter()
-                .filter(|&h| *h != httparse::EMPTY_HEADER)
-                .filter_map(|h| {
-                    if let Ok(val) = core::str::from_utf8(h.value) {
-                        Some(HttpServerHeader {
-                            name: h.name.to_string(),
-                            value: val.to_string(),
-                        })
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
-        };
+            chunked.into_body()
+        }
+    };
+    debug!(logger, "Whole body received.");
+
+    Ok((HttpServerRequest { body, ..head.request }, head.version))
+}
+
+/// Appends the next data from the socket to `buf`.
+async fn read_more<S>(logger: &Logger, socket: &mut S, buf: &mut Vec<u8>) -> Result<(), HttpServerError>
+where
+    S: TcpSocket,
+{
+    let mut chunk = [0; 64];
+    match socket.read(&mut chunk).await {
+        Ok(0) => Err(HttpServerError::ConnectionClosed),
+        Ok(b) => {
+            buf.extend(&chunk[0..b]);
+            debug!(logger, "Received {} bytes", b);
+            Ok(())
+        }
+        Err(e) => {
+            error!(logger, "Network error during the request: {:?}", e);
+            Err(e.into())
+        }
+    }
+}
+
+/// How the end of the request body is found.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RequestBody {
+    Length(usize),
+    Chunked
+}
+
+struct RequestHead {
+    len: usize,
+    /// Without the body.
+    request: HttpServerRequest,
+    /// The minor HTTP version.
+    version: u8,
+    body: RequestBody,
+    /// The client waits for a `100 Continue` before sending the body.
+    expect_continue: bool
+}
+
+/// The request head at the start of `buf`, `None` until it is complete.
+fn parse_head(logger: &Logger, buf: &[u8], limits: &RequestLimits) -> Result<Option<RequestHead>, HttpServerError> {
+    let mut headers_buffer = vec![httparse::EMPTY_HEADER; limits.max_headers];
+
+    let mut r = httparse::Request::new(&mut headers_buffer);
+    let n = match r.parse(buf) {
+        Ok(httparse::Status::Complete(size)) if size > limits.max_header_bytes => return Err(HttpServerError::HeadersTooLarge),
+        Ok(httparse::Status::Complete(size)) => size,
+        Ok(httparse::Status::Partial) => {
+            debug!(logger, "Partial headers, getting more data");
+            return Ok(None);
+        }
+        Err(httparse::Error::TooManyHeaders) => return Err(HttpServerError::TooManyHeaders),
+        Err(httparse::Error::Version) => return Err(HttpServerError::UnsupportedVersion),
+        Err(e) => {
+            error!(logger, "HTTP Parser error: {:?}", e);
+            return Err(HttpServerError::InvalidRequest);
+        }
+    };
+
+    let content_length = content_length(r.headers)?;
+    let transfer_encoding = header_tokens(r.headers, "Transfer-Encoding").map_err(|_| HttpServerError::InvalidTransferEncoding)?;
+    let body = match (transfer_encoding.is_empty(), content_length) {
+        (true, Some(length)) => RequestBody::Length(length),
+        // the body of these methods would be read up to the end of the connection
+        (true, None) if matches!(r.method, Some("POST" | "PUT" | "PATCH")) => return Err(HttpServerError::LengthRequired),
+        (true, None) => RequestBody::Length(0),
+        (false, Some(_)) => return Err(HttpServerError::ConflictingLength),
+        (false, None) => match transfer_encoding.split_last() {
+            // chunked is applied last, and only once
+            Some((last, codings)) if last.eq_ignore_ascii_case("chunked") && !codings.iter().any(|c| c.eq_ignore_ascii_case("chunked")) => {
+                if !codings.is_empty() {
+                    // other codings, like "gzip, chunked", can't be decoded
+                    return Err(HttpServerError::UnsupportedTransferEncoding);
+                }
+                RequestBody::Chunked
+            },
+            _ => return Err(HttpServerError::InvalidTransferEncoding)
+        }
+    };
+    let expect_continue = r.version == Some(1) && header_tokens(r.headers, "Expect").map_err(|_| HttpServerError::InvalidRequest)?.iter().any(|t| t.eq_ignore_ascii_case("100-continue"));
+
+    let req = HttpServerRequest {
+        method: r.method.map(|m| m.to_string()),
+        path: r.path.map(|p| p.to_string()),
+        body: vec![],
+        headers: r.headers
+            .iter()
+            .filter_map(|h| {
+                if let Ok(val) = core::str::from_utf8(h.value) {
+                    Some(HttpServerHeader {
+                        name: h.name.to_string(),
+                        value: val.to_string(),
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect(),
+    };
 
-        return Ok(req);
+    Ok(Some(RequestHead { len: n, request: req, version: r.version.unwrap_or(1), body, expect_continue }))
+}
+
+/// The comma separated values of all the headers with the name, an error if
+/// one isn't UTF-8.
+fn header_tokens<'a>(headers: &[httparse::Header<'a>], name: &str) -> Result<Vec<&'a str>, core::str::Utf8Error> {
+    let mut tokens = vec![];
+    for h in headers.iter().filter(|h| h.name.eq_ignore_ascii_case(name)) {
+        tokens.extend(core::str::from_utf8(h.value)?.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()));
+    }
+    Ok(tokens)
+}
+
+/// The `Content-Length` of the request. Repeated ones must agree, and only
+/// digits are accepted, a `+5` or `-1` is refused.
+fn content_length(headers: &[httparse::Header]) -> Result<Option<usize>, HttpServerError> {
+    let mut length = None;
+    for h in headers.iter().filter(|h| h.name.eq_ignore_ascii_case("Content-Length")) {
+        let value = core::str::from_utf8(h.value).map_err(|_| HttpServerError::InvalidContentLength)?.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(HttpServerError::InvalidContentLength);
+        }
+        let value = value.parse::<usize>().map_err(|_| HttpServerError::InvalidContentLength)?;
+        match length {
+            Some(l) if l != value => return Err(HttpServerError::ConflictingLength),
+            _ => length = Some(value)
+        }
     }
+    Ok(length)
 }
 
 pub struct HttpContext<S>
@@ -203,7 +602,42 @@ where
 {
     pub logger: Logger,
     pub request: HttpServerRequest,
-    pub socket: S,
+    /// The connection of the request, a handler can take it. A response sent
+    /// on it directly, not through `write`, closes the connection after the
+    /// handler.
+    pub socket: Option<S>,
+    response: Box<ResponseFraming>,
+    /// Hands the connection back to the server once the handler is done with it.
+    returned: Option<oneshot::Sender<(S, Box<ResponseFraming>)>>
+}
+
+impl<S> HttpContext<S>
+where
+    S: TcpSocket,
+{
+    /// A context for a request read outside of the server, the connection is
+    /// closed after the response.
+    pub fn new(logger: Logger, request: HttpServerRequest, socket: S) -> Self {
+        HttpContext {
+            logger,
+            request,
+            socket: Some(socket),
+            response: Box::new(ResponseFraming::new(false, false, None)),
+            returned: None
+        }
+    }
+}
+
+impl<S> Drop for HttpContext<S>
+where
+    S: TcpSocket,
+{
+    fn drop(&mut self) {
+        if let (Some(socket), Some(returned)) = (self.socket.take(), self.returned.take()) {
+            let response = core::mem::replace(&mut self.response, Box::new(ResponseFraming::new(false, false, None)));
+            let _ = returned.send((socket, response));
+        }
+    }
 }
 
 #[async_trait]
@@ -212,7 +646,62 @@ where
     S: TcpSocket,
 {
     async fn write(&mut self, data: &[u8]) -> Result<(), TcpError> {
-        self.socket.send(data).await?;
-        Ok(())
+        match self.socket {
+            Some(ref mut socket) => self.response.write(socket, data).await,
+            None => Err(TcpError::Closed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::Discard;
+
+    fn head(request: &[u8]) -> Result<Option<RequestHead>, HttpServerError> {
+        parse_head(&Logger::root(Discard, o!()), request, &RequestLimits::default())
+    }
+
+    fn error(request: &[u8]) -> Option<HttpServerError> {
+        head(request).err()
+    }
+
+    #[test]
+    fn request_head() {
+        let h = head(b"POST /v HTTP/1.0\r\nContent-Length: 4\r\ncontent-length: 4\r\n\r\nbody").unwrap().unwrap();
+        assert_eq!((RequestBody::Length(4), 0, Some("/v")), (h.body, h.version, h.request.path.as_deref()));
+        assert_eq!(h.len, b"POST /v HTTP/1.0\r\nContent-Length: 4\r\ncontent-length: 4\r\n\r\n".len());
+        assert!(head(b"GET / HTTP/1.1\r\nHost: dev").unwrap().is_none());
+
+        assert!(matches!(error(b"GET /\x01 HTTP/1.1\r\n\r\n"), Some(HttpServerError::InvalidRequest)));
+        assert!(matches!(error(b"GET / HTTP/2.0\r\n\r\n"), Some(HttpServerError::UnsupportedVersion)));
+        for length in [&b"+5"[..], b"-1", b"", b"0x10", b"99999999999999999999999"] {
+            let mut request = b"POST / HTTP/1.1\r\nContent-Length: ".to_vec();
+            request.extend_from_slice(length);
+            request.extend_from_slice(b"\r\n\r\n");
+            assert!(matches!(error(&request), Some(HttpServerError::InvalidContentLength)));
+        }
+        assert!(matches!(error(b"POST / HTTP/1.1\r\nContent-Length: 4\r\nContent-Length: 5\r\n\r\n"), Some(HttpServerError::ConflictingLength)));
+        assert!(matches!(error(b"POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n"), Some(HttpServerError::ConflictingLength)));
+        assert!(matches!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"), Some(HttpServerError::UnsupportedTransferEncoding)));
+        assert!(matches!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n"), Some(HttpServerError::InvalidTransferEncoding)));
+        assert!(matches!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n"), Some(HttpServerError::InvalidTransferEncoding)));
+        assert!(matches!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: \xffchunked\r\n\r\n"), Some(HttpServerError::InvalidTransferEncoding)));
+        for method in ["POST", "PUT", "PATCH"] {
+            let request = alloc::format!("{} / HTTP/1.1\r\n\r\n", method);
+            assert!(matches!(error(request.as_bytes()), Some(HttpServerError::LengthRequired)));
+        }
+        assert_eq!(RequestBody::Length(0), head(b"DELETE / HTTP/1.1\r\n\r\n").unwrap().unwrap().body);
+        let h = head(b"POST / HTTP/1.1\r\nTransfer-Encoding: Chunked\r\nExpect: 100-continue\r\n\r\n").unwrap().unwrap();
+        assert_eq!((RequestBody::Chunked, true), (h.body, h.expect_continue));
+
+        let mut request = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..61 {
+            request.extend_from_slice(alloc::format!("X-{}: a\r\n", i).as_bytes());
+        }
+        request.extend_from_slice(b"\r\n");
+        assert!(matches!(error(&request), Some(HttpServerError::TooManyHeaders)));
+        let request = alloc::format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(9000));
+        assert!(matches!(error(request.as_bytes()), Some(HttpServerError::HeadersTooLarge)));
     }
 }
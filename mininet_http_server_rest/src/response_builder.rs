@@ -1,6 +1,6 @@
 use core::ops::Deref;
 use alloc::vec::Vec;
-use mininet_base::{req::HttpServerHeader, resp::{HttpResponseWriter, HttpStatusCodes}, stack::TcpSocket};
+use mininet_base::{req::HttpServerHeader, resp::{HttpResponseWriter, HttpStatusCodes}};
 use mininet_http_server::HttpContext;
 
 use crate::{RestError, extras::Extras, middleware::HttpMiddlewareContext};
@@ -39,8 +39,7 @@ where
         let (http_code, http_code_str) = code.to_http();
 
         self.http_ctx
-            .socket
-            .send(format!("HTTP/1.1 {} {}\r\n", http_code, http_code_str).as_bytes())
+            .write(format!("HTTP/1.1 {} {}\r\n", http_code, http_code_str).as_bytes())
             .await?;
 
         if let Some(content_type) = content_type {
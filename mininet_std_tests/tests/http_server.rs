@@ -0,0 +1,362 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures::future::{join, select, Either};
+use futures::pin_mut;
+use mininet_base::resp::{HttpResponseWriter, HttpStatusCodes};
+use mininet_base::stack::{TcpError, TcpSocket, TcpStack};
+use mininet_base::std::{StdTcpSocket, StdTcpSocketListener, StdTcpStack};
+use mininet_http_server::{http_server, http_server_with_config, HttpContext, HttpServerConfig, RequestLimits, WhenBusy};
+use mininet_std_tests::StdEnv;
+use slog::{o, Drain, Logger};
+
+fn logger() -> Logger {
+    let decorator = slog_term::TermDecorator::new().build();
+    let drain = slog_term::CompactFormat::new(decorator).build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    slog::Logger::root(drain, o!())
+}
+
+fn addr(port: u16) -> mininet_base::addr::SocketAddr {
+    mininet_base::addr::SocketAddrV4::new(mininet_base::addr::Ipv4Addr::new(127, 0, 0, 1), port).into()
+}
+
+/// Sends the raw request and reads until the server closes the connection.
+async fn raw_request(port: u16, request: &[u8]) -> Result<String, TcpError> {
+    let mut socket = StdTcpStack.create_socket_connected(addr(port)).await?;
+    socket.send(request).await?;
+    let response = socket.read_to_end().await?;
+    Ok(String::from_utf8_lossy(&response).to_string())
+}
+
+async fn slow_or_fast(ctx: HttpContext<StdTcpSocket>) {
+    if ctx.request.path.as_deref() == Some("/slow") {
+        async_io::Timer::after(Duration::from_millis(400)).await;
+    }
+    let body = ctx.request.path.clone().unwrap_or_default();
+    let _ = ctx.http_reply(HttpStatusCodes::Ok.into(), "text/plain", &body).await;
+}
+
+/// Runs the client against a server with the configuration.
+async fn with_server<H, Fut, C>(port: u16, config: HttpServerConfig, handler: H, client: C) -> Result<(), TcpError>
+    where H: Fn(HttpContext<StdTcpSocket>) -> Fut, Fut: Future<Output = ()>, C: Future<Output = Result<(), TcpError>>
+{
+    let logger = logger();
+    let listener = StdTcpStack.create_socket_listener(addr(port)).await?;
+    let server = http_server_with_config(&logger, StdEnv, listener, handler, config);
+
+    pin_mut!(server);
+    pin_mut!(client);
+    match select(server, client).await {
+        Either::Left(_) => panic!("The server stopped."),
+        Either::Right((r, _)) => r
+    }
+}
+
+fn assert_send<T: Send>(_: T) {}
+
+/// Not run, the servers must be `Send` to be spawned on a multi-threaded executor.
+#[allow(dead_code)]
+fn servers_are_send(listener: StdTcpSocketListener, other: StdTcpSocketListener) {
+    let logger = logger();
+    assert_send(http_server(&logger, StdEnv, listener, slow_or_fast, None));
+    assert_send(http_server_with_config(&logger, StdEnv, other, slow_or_fast, HttpServerConfig::default()));
+}
+
+/// A fast request sent while a slow one is handled, returns its response and its duration.
+async fn slow_then_fast(port: u16) -> Result<(String, String, Duration), TcpError> {
+    let slow = raw_request(port, b"GET /slow HTTP/1.1\r\nHost: device\r\nConnection: close\r\n\r\n");
+    let fast = async {
+        async_io::Timer::after(Duration::from_millis(50)).await;
+        let start = Instant::now();
+        let response = raw_request(port, b"GET /fast HTTP/1.1\r\nHost: device\r\nConnection: close\r\n\r\n").await?;
+        Ok::<_, TcpError>((response, start.elapsed()))
+    };
+    let (slow, fast) = join(slow, fast).await;
+    let (fast, elapsed) = fast?;
+    Ok((slow?, fast, elapsed))
+}
+
+#[tokio::test]
+async fn server_concurrent_connections() -> Result<(), TcpError> {
+    let config = HttpServerConfig { max_connections: 2, ..Default::default() };
+    with_server(18049, config, slow_or_fast, async {
+        let (slow, fast, elapsed) = slow_then_fast(18049).await?;
+        assert!(slow.starts_with("HTTP/1.1 200 OK") && slow.ends_with("/slow"), "{}", slow);
+        assert!(fast.ends_with("/fast"), "{}", fast);
+        assert!(elapsed < Duration::from_millis(200), "{:?}", elapsed);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_connection_backlog() -> Result<(), TcpError> {
+    let config = HttpServerConfig { max_connections: 1, when_busy: WhenBusy::Backlog, ..Default::default() };
+    with_server(18050, config, slow_or_fast, async {
+        // waits until the slow one is done
+        let (_, fast, elapsed) = slow_then_fast(18050).await?;
+        assert!(fast.ends_with("/fast"), "{}", fast);
+        assert!(elapsed > Duration::from_millis(250), "{:?}", elapsed);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_busy_rejection() -> Result<(), TcpError> {
+    let config = HttpServerConfig { max_connections: 1, when_busy: WhenBusy::Reject, ..Default::default() };
+    with_server(18051, config, slow_or_fast, async {
+        let (slow, fast, elapsed) = slow_then_fast(18051).await?;
+        assert!(slow.ends_with("/slow"), "{}", slow);
+        assert!(fast.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", fast);
+        // the connection is closed after a short drain
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+
+        // the request is read while the 503 is sent, so it isn't lost in a reset
+        let slow = raw_request(18051, b"GET /slow HTTP/1.1\r\nHost: device\r\nConnection: close\r\n\r\n");
+        let upload = async {
+            async_io::Timer::after(Duration::from_millis(50)).await;
+            let mut request = b"POST /fast HTTP/1.1\r\nHost: device\r\nContent-Length: 16384\r\nConnection: close\r\n\r\n".to_vec();
+            request.resize(request.len() + 16384, b'x');
+            raw_request(18051, &request).await
+        };
+        let (slow, upload) = join(slow, upload).await;
+        assert!(slow?.ends_with("/slow"));
+        let upload = upload?;
+        assert!(upload.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", upload);
+
+        // accepted again once there is room
+        let fast = raw_request(18051, b"GET /fast HTTP/1.1\r\nHost: device\r\nConnection: close\r\n\r\n").await?;
+        assert!(fast.ends_with("/fast"), "{}", fast);
+        Ok(())
+    }).await
+}
+
+/// Reads from the socket until the data ends with `end`.
+async fn read_until(socket: &mut StdTcpSocket, end: &[u8]) -> Result<String, TcpError> {
+    let mut data = vec![];
+    let mut buf = [0; 512];
+    while !data.ends_with(end) {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&data).to_string())
+}
+
+#[tokio::test]
+async fn server_keep_alive() -> Result<(), TcpError> {
+    with_server(18052, HttpServerConfig::default(), slow_or_fast, async {
+        let mut socket = StdTcpStack.create_socket_connected(addr(18052)).await?;
+        for path in ["/first", "/second"] {
+            socket.send(format!("GET {} HTTP/1.1\r\nHost: device\r\n\r\n", path).as_bytes()).await?;
+            // without a length, the body is chunked to keep the connection
+            let response = read_until(&mut socket, b"0\r\n\r\n").await?;
+            assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{}", response);
+            assert!(response.ends_with(&format!("{}\r\n{}\r\n0\r\n\r\n", path.len(), path)), "{}", response);
+        }
+        socket.send(b"GET /last HTTP/1.1\r\nHost: device\r\nConnection: close\r\n\r\n").await?;
+        let response = String::from_utf8_lossy(&socket.read_to_end().await?).to_string();
+        assert!(response.contains("Connection: close\r\n") && response.ends_with("\r\n\r\n/last"), "{}", response);
+
+        // HTTP/1.0 closes by default, and keeps the connection only when asked to
+        let response = raw_request(18052, b"GET /old HTTP/1.0\r\n\r\n").await?;
+        assert!(response.contains("Connection: close\r\n") && response.ends_with("/old"), "{}", response);
+
+        let mut socket = StdTcpStack.create_socket_connected(addr(18052)).await?;
+        socket.send(b"GET /old HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await?;
+        let response = String::from_utf8_lossy(&socket.read_to_end().await?).to_string();
+        assert!(response.contains("Connection: close\r\n") && response.ends_with("/old"), "{}", response);
+        Ok(())
+    }).await
+}
+
+async fn with_length(mut ctx: HttpContext<StdTcpSocket>) {
+    let body = format!("{} {}", ctx.request.path.clone().unwrap_or_default(), String::from_utf8_lossy(&ctx.request.body));
+    let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len());
+    let _ = ctx.write(head.as_bytes()).await;
+    let _ = ctx.write(body.as_bytes()).await;
+}
+
+#[tokio::test]
+async fn server_keep_alive_http_10() -> Result<(), TcpError> {
+    with_server(18053, HttpServerConfig::default(), with_length, async {
+        let mut socket = StdTcpStack.create_socket_connected(addr(18053)).await?;
+        for path in ["/a", "/b"] {
+            socket.send(format!("GET {} HTTP/1.0\r\nConnection: keep-alive\r\n\r\n", path).as_bytes()).await?;
+            let response = read_until(&mut socket, format!("{} ", path).as_bytes()).await?;
+            assert!(response.contains("Content-Length: 3\r\nConnection: keep-alive\r\n\r\n"), "{}", response);
+        }
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_head_request() -> Result<(), TcpError> {
+    with_server(18061, HttpServerConfig::default(), with_length, async {
+        let mut socket = StdTcpStack.create_socket_connected(addr(18061)).await?;
+        // the body written by the handler is dropped, and the connection kept
+        socket.send(b"HEAD /head HTTP/1.1\r\n\r\nGET /next HTTP/1.1\r\nConnection: close\r\n\r\n").await?;
+        let response = String::from_utf8_lossy(&socket.read_to_end().await?).to_string();
+        let expected = "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\n/next ";
+        assert_eq!(expected, response);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_pipelined_requests() -> Result<(), TcpError> {
+    let config = HttpServerConfig { max_requests_per_connection: 3, ..Default::default() };
+    with_server(18054, config, with_length, async {
+        let mut socket = StdTcpStack.create_socket_connected(addr(18054)).await?;
+        socket.send(b"POST /one HTTP/1.1\r\nContent-Length: 5\r\n\r\nfirstGET /two HTTP/1.1\r\n\r\nPOST /three HTTP/1.1\r\nContent-Length: 4\r\n\r\nlast").await?;
+
+        // answered in order, the connection is closed after the last one allowed
+        let response = String::from_utf8_lossy(&socket.read_to_end().await?).to_string();
+        let bodies: Vec<_> = response.split("HTTP/1.1 200 OK\r\n").skip(1).map(|r| r.split("\r\n\r\n").nth(1).unwrap()).collect();
+        assert_eq!(vec!["/one first", "/two ", "/three last"], bodies);
+        assert!(response.ends_with("Content-Length: 11\r\nConnection: close\r\n\r\n/three last"), "{}", response);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_idle_connections_at_capacity() -> Result<(), TcpError> {
+    let config = HttpServerConfig { max_connections: 2, ..Default::default() };
+    with_server(18059, config, with_length, async {
+        let mut kept = vec![];
+        for path in ["/first", "/second"] {
+            let mut socket = StdTcpStack.create_socket_connected(addr(18059)).await?;
+            socket.send(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes()).await?;
+            read_until(&mut socket, format!("{} ", path).as_bytes()).await?;
+            kept.push(socket);
+        }
+
+        // the oldest idle connection is closed for it, without waiting for the idle timeout
+        let start = Instant::now();
+        let response = raw_request(18059, b"GET /third HTTP/1.1\r\nConnection: close\r\n\r\n").await?;
+        assert!(response.ends_with("/third "), "{}", response);
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+        assert!(kept[0].read_to_end().await?.is_empty());
+
+        let second = &mut kept[1];
+        second.send(b"GET /again HTTP/1.1\r\nConnection: close\r\n\r\n").await?;
+        assert!(read_until(second, b"/again ").await?.ends_with("/again "));
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_idle_timeout() -> Result<(), TcpError> {
+    let config = HttpServerConfig { idle_timeout: Some(Duration::from_millis(200)), ..Default::default() };
+    with_server(18055, config, with_length, async {
+        let mut socket = StdTcpStack.create_socket_connected(addr(18055)).await?;
+        socket.send(b"GET /idle HTTP/1.1\r\n\r\n").await?;
+        read_until(&mut socket, b"/idle ").await?;
+
+        let start = Instant::now();
+        assert!(socket.read_to_end().await?.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_request_errors() -> Result<(), TcpError> {
+    let config = HttpServerConfig {
+        limits: RequestLimits { max_body_bytes: 16, ..Default::default() },
+        ..Default::default()
+    };
+    with_server(18056, config, with_length, async {
+        let long_header = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(9000));
+        let requests: [(&[u8], &str); 9] = [
+            (b"GET /\x01 HTTP/1.1\r\n\r\n", "400 Bad Request"),
+            (b"POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n", "400 Bad Request"),
+            (b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n", "400 Bad Request"),
+            (b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n", "501 Not Implemented"),
+            (b"POST / HTTP/1.1\r\nTransfer-Encoding: \xffchunked\r\n\r\n", "400 Bad Request"),
+            (b"PUT / HTTP/1.1\r\n\r\n", "411 Length Required"),
+            (b"POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n", "413 Payload Too Large"),
+            (long_header.as_bytes(), "431 Request Header Fields Too Large"),
+            (b"GET / HTTP/2.0\r\n\r\n", "505 HTTP Version Not Supported")
+        ];
+        for (request, status) in requests {
+            let response = raw_request(18056, request).await?;
+            assert_eq!(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status), response);
+        }
+
+        // more than the announced length is taken as the next request
+        let response = raw_request(18056, b"POST /short HTTP/1.1\r\nContent-Length: 2\r\n\r\nabcd\r\n\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n/short ab"), "{}", response);
+        assert!(response.ends_with("HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"), "{}", response);
+        Ok(())
+    }).await
+}
+
+#[tokio::test]
+async fn server_unlimited_body() -> Result<(), TcpError> {
+    let logger = logger();
+    let listener = StdTcpStack.create_socket_listener(addr(18062)).await?;
+    let server = http_server(&logger, StdEnv, listener, with_length, None);
+    let client = async {
+        // larger than the default limit of the configuration
+        let mut request = b"POST /large HTTP/1.1\r\nContent-Length: 100000\r\nConnection: close\r\n\r\n".to_vec();
+        request.extend_from_slice(&[b'a'; 100000]);
+        let response = raw_request(18062, &request).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 100007\r\n"), "{}", &response[..100]);
+        Ok(())
+    };
+
+    pin_mut!(server);
+    pin_mut!(client);
+    match select(server, client).await {
+        Either::Left(_) => panic!("The server stopped."),
+        Either::Right((r, _)) => r
+    }
+}
+
+#[tokio::test]
+async fn server_chunked_request() -> Result<(), TcpError> {
+    let config = HttpServerConfig {
+        limits: RequestLimits { max_chunk_bytes: 8, ..Default::default() },
+        ..Default::default()
+    };
+    with_server(18057, config, with_length, async {
+        let mut socket = StdTcpStack.create_socket_connected(addr(18057)).await?;
+        socket.send(b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;last\r\n world\r\n0\r\n\r\nGET /next HTTP/1.1\r\n\r\n").await?;
+        let response = read_until(&mut socket, b"/next ").await?;
+        assert!(response.contains("\r\n\r\n/upload hello world"), "{}", response);
+
+        // the body follows the 100 Continue
+        socket.send(b"POST /expect HTTP/1.1\r\nTransfer-Encoding: chunked\r\nExpect: 100-continue\r\n\r\n").await?;
+        let response = read_until(&mut socket, b"\r\n\r\n").await?;
+        assert_eq!("HTTP/1.1 100 Continue\r\n\r\n", response);
+        socket.send(b"4\r\ndata\r\n0\r\n\r\n").await?;
+        let response = read_until(&mut socket, b"/expect data").await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+        let response = raw_request(18057, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n9\r\ntoo large\r\n0\r\n\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", response);
+        let response = raw_request(18057, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n-1\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+        Ok(())
+    }).await
+}
+
+/// Answers on the socket taken from the context, after the context is gone.
+async fn take_socket(mut ctx: HttpContext<StdTcpSocket>) {
+    let mut socket = ctx.socket.take().unwrap();
+    drop(ctx);
+    let _ = socket.send(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nraw").await;
+}
+
+#[tokio::test]
+async fn server_taken_socket() -> Result<(), TcpError> {
+    with_server(18060, HttpServerConfig::default(), take_socket, async {
+        // the server doesn't get the connection back, it is closed after the handler
+        let response = raw_request(18060, b"GET / HTTP/1.1\r\n\r\n").await?;
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nraw", response);
+        Ok(())
+    }).await
+}
@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use futures::future::{select, Either};
+use futures::pin_mut;
+use mininet_base::stack::{TcpListen, TcpSocket, TcpStack};
+use mininet_base::std::{StdTcpSocket, StdTcpStack};
+use mininet_std_tests::StdEnv;
+use mininet_websocket_client::frame::apply_mask;
+use mininet_websocket_client::handshake::accept_key;
+use mininet_websocket_client::{CloseFrame, FrameHeader, Message, Opcode, WebSocket, WebSocketConfig, WebSocketError};
+use slog::{o, Drain, Logger};
+
+fn logger() -> Logger {
+    let decorator = slog_term::TermDecorator::new().build();
+    let drain = slog_term::CompactFormat::new(decorator).build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    slog::Logger::root(drain, o!())
+}
+
+/// A random number from the hasher seeds of the standard library.
+fn random() -> u32 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new().build_hasher().finish() as u32
+}
+
+/// The server side of a connection, frames are written unmasked.
+struct Peer {
+    socket: StdTcpSocket,
+    input: Vec<u8>,
+    key: String
+}
+
+impl Peer {
+    async fn accept(socket: StdTcpSocket) -> Peer {
+        let mut peer = Peer { socket, input: vec![], key: String::new() };
+        while !peer.input.windows(4).any(|w| w == b"\r\n\r\n") {
+            peer.fill().await;
+        }
+        let request = String::from_utf8(std::mem::take(&mut peer.input)).unwrap();
+        assert!(request.starts_with("GET /tunnel HTTP/1.1\r\nHost: 127.0.0.1:18044\r\n"), "{}", request);
+        assert!(request.contains("Sec-WebSocket-Protocol: tunnel.v2, tunnel.v1\r\n"));
+        assert!(request.contains("Authorization: Bearer t0k3n\r\n"));
+
+        let key = request.lines().find_map(|l| l.strip_prefix("Sec-WebSocket-Key: ")).unwrap();
+        let reply = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\nSec-WebSocket-Protocol: tunnel.v1\r\n\r\n",
+            accept_key(key)
+        );
+        peer.key = key.to_string();
+        peer.socket.send(reply.as_bytes()).await.unwrap();
+        peer
+    }
+
+    async fn fill(&mut self) {
+        let mut buf = [0; 512];
+        let n = self.socket.read(&mut buf).await.unwrap();
+        assert!(n > 0, "The client closed the connection.");
+        self.input.extend_from_slice(&buf[..n]);
+    }
+
+    async fn read_frame(&mut self) -> (FrameHeader, Vec<u8>) {
+        loop {
+            if let Some((header, size)) = FrameHeader::parse(&self.input).unwrap() {
+                let end = size + header.len as usize;
+                if self.input.len() >= end {
+                    let mut payload: Vec<u8> = self.input.drain(..end).skip(size).collect();
+                    apply_mask(&mut payload, header.mask.expect("The client must mask its frames."));
+                    return (header, payload);
+                }
+            }
+            self.fill().await;
+        }
+    }
+
+    async fn write_frame(&mut self, fin: bool, opcode: Opcode, payload: &[u8]) {
+        let mut frame = vec![];
+        FrameHeader { fin, opcode, mask: None, len: payload.len() as u64 }.encode(&mut frame);
+        frame.extend_from_slice(payload);
+        self.socket.send(&frame).await.unwrap();
+    }
+}
+
+async fn tunnel_server(mut stack: StdTcpStack) {
+    let addr = mininet_base::addr::SocketAddrV4::new(mininet_base::addr::Ipv4Addr::new(127, 0, 0, 1), 18044);
+    let mut listener = stack.create_socket_listener(addr.into()).await.unwrap();
+
+    let mut peer = Peer::accept(listener.accept().await.unwrap().0).await;
+    let first_key = peer.key.clone();
+
+    // a ping from the server is answered
+    peer.write_frame(true, Opcode::Ping, b"hb").await;
+    let text = peer.read_frame().await;
+    let pong = peer.read_frame().await;
+    let (pong, text) = if pong.0.opcode == Opcode::Pong { (pong, text) } else { (text, pong) };
+    assert_eq!((Opcode::Pong, b"hb".as_slice()), (pong.0.opcode, pong.1.as_slice()));
+
+    // echoed in fragments, with a ping in between
+    assert_eq!((Opcode::Text, true, b"hello".as_slice()), (text.0.opcode, text.0.fin, text.1.as_slice()));
+    peer.write_frame(false, Opcode::Text, b"hel").await;
+    peer.write_frame(true, Opcode::Ping, b"").await;
+    peer.write_frame(true, Opcode::Continuation, b"lo").await;
+    assert_eq!(Opcode::Pong, peer.read_frame().await.0.opcode);
+
+    // a fragmented upload, echoed as a single frame
+    let mut upload = vec![];
+    let mut frames = vec![];
+    loop {
+        let (header, payload) = peer.read_frame().await;
+        frames.push(header.opcode);
+        upload.extend_from_slice(&payload);
+        if header.fin {
+            break;
+        }
+    }
+    assert_eq!(vec![Opcode::Binary, Opcode::Continuation, Opcode::Continuation], frames);
+    peer.write_frame(true, Opcode::Binary, &upload).await;
+
+    // quiet until the client pings
+    let (header, _) = peer.read_frame().await;
+    assert_eq!(Opcode::Ping, header.opcode);
+    peer.write_frame(true, Opcode::Pong, b"").await;
+
+    let (header, payload) = peer.read_frame().await;
+    assert_eq!((Opcode::Close, b"\x03\xe8bye".as_slice()), (header.opcode, payload.as_slice()));
+    peer.write_frame(true, Opcode::Close, b"\x03\xe8").await;
+
+    // too large for the client, which closes with 1009
+    let mut peer = Peer::accept(listener.accept().await.unwrap().0).await;
+    // a new nonce for every connection
+    assert_ne!(first_key, peer.key);
+    peer.write_frame(true, Opcode::Binary, &[0; 5000]).await;
+    let (header, payload) = peer.read_frame().await;
+    assert_eq!((Opcode::Close, b"\x03\xf1".as_slice()), (header.opcode, payload.as_slice()));
+
+    // closed by the server
+    let mut peer = Peer::accept(listener.accept().await.unwrap().0).await;
+    peer.write_frame(true, Opcode::Close, b"\x03\xe9going away").await;
+    let (header, payload) = peer.read_frame().await;
+    assert_eq!((Opcode::Close, b"\x03\xe9".as_slice()), (header.opcode, payload.as_slice()));
+
+    futures::future::pending::<()>().await;
+}
+
+#[tokio::test]
+async fn websocket_client() -> Result<(), WebSocketError> {
+    let logger = logger();
+    let server = tunnel_server(StdTcpStack);
+
+    let client = async {
+        let config = WebSocketConfig {
+            max_message_size: 4096,
+            ping_interval: Some(Duration::from_millis(500)),
+            pong_timeout: Duration::from_secs(1),
+            ..WebSocketConfig::new(random)
+        }
+        .with_protocol("tunnel.v2")
+        .with_protocol("tunnel.v1")
+        .with_header("Authorization", "Bearer t0k3n");
+        let url = "ws://127.0.0.1:18044/tunnel";
+
+        // the server may not listen yet
+        let mut ws = loop {
+            match WebSocket::connect(&logger, &mut StdTcpStack, StdEnv, url, config.clone()).await {
+                Err(WebSocketError::TcpError(_)) => async_io::Timer::after(Duration::from_millis(20)).await,
+                r => break r?
+            };
+        };
+        assert_eq!(Some("tunnel.v1"), ws.protocol());
+
+        ws.send_text("hello").await?;
+        assert_eq!(Message::Text("hello".to_string()), ws.recv().await?);
+
+        let upload: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        ws.send_binary(&upload).await?;
+        assert_eq!(Message::Binary(upload), ws.recv().await?);
+
+        // the keepalive runs while waiting, the wait can be cancelled
+        let quiet = async_io::Timer::after(Duration::from_millis(700));
+        match select(Box::pin(ws.recv()), quiet).await {
+            Either::Left((r, _)) => panic!("Unexpected message {:?}", r),
+            Either::Right(_) => {}
+        }
+
+        ws.close(1000, "bye").await?;
+        assert!(!ws.is_open());
+        assert!(matches!(ws.send_text("late").await, Err(WebSocketError::Closed)));
+
+        let mut ws = WebSocket::connect(&logger, &mut StdTcpStack, StdEnv, url, config.clone()).await?;
+        assert!(matches!(ws.recv().await, Err(WebSocketError::MessageTooLarge)));
+
+        let mut ws = WebSocket::connect(&logger, &mut StdTcpStack, StdEnv, url, config).await?;
+        let close = CloseFrame { code: 1001, reason: "going away".to_string() };
+        assert_eq!(Message::Close(Some(close)), ws.recv().await?);
+        assert!(matches!(ws.recv().await, Err(WebSocketError::Closed)));
+
+        assert!(matches!(
+            WebSocket::connect(&logger, &mut StdTcpStack, StdEnv, "wss://127.0.0.1:18044/tunnel", WebSocketConfig::new(random)).await,
+            Err(WebSocketError::UnsupportedUrlScheme(_))
+        ));
+
+        Ok(())
+    };
+
+    pin_mut!(server);
+    pin_mut!(client);
+    match select(server, client).await {
+        Either::Left(_) => panic!("The server stopped."),
+        Either::Right((r, _)) => r
+    }
+}
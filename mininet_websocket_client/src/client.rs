@@ -0,0 +1,470 @@
+use core::time::Duration;
+
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use mininet_base::{addr::{Ipv4Addr, SocketAddr, SocketAddrV4}, stack::{SystemEnvironment, TcpError, TcpSocket, TcpStack, with_timeout}, url::{Authority, Url}};
+use slog::{Logger, debug, info, o, warn};
+
+use crate::{WebSocketError, frame::{FrameHeader, Opcode, apply_mask}, handshake::{check_response, generate_key, request_head}};
+
+/// The largest upgrade response accepted.
+const MAX_HANDSHAKE_SIZE: usize = 4096;
+
+/// Settings of a connection.
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    /// Larger messages from the server close the connection, this bounds the
+    /// memory used for a single message.
+    pub max_message_size: usize,
+    /// Sent messages are split into frames of this size.
+    pub fragment_size: usize,
+    /// A ping is sent after the connection was quiet for this long.
+    pub ping_interval: Option<Duration>,
+    /// The connection is considered lost if nothing arrives this long after a ping.
+    pub pong_timeout: Duration,
+    /// Connecting and the upgrade request.
+    pub handshake_timeout: Option<Duration>,
+    /// The wait for the server to confirm a close.
+    pub close_timeout: Duration,
+    /// Subprotocols offered in `Sec-WebSocket-Protocol`, in order of preference.
+    pub protocols: Vec<String>,
+    /// Additional headers of the upgrade request, like `Authorization`.
+    pub headers: Vec<(String, String)>,
+    /// Source of the `Sec-WebSocket-Key` nonce and of the masking key of
+    /// every frame. RFC 6455 requires both to be unpredictable, so this
+    /// should read a hardware random number generator. A fixed sequence is
+    /// only meant for tests.
+    pub random: fn() -> u32
+}
+
+impl WebSocketConfig {
+    /// The default settings, with the source of the random numbers, which
+    /// has no sensible default.
+    pub fn new(random: fn() -> u32) -> Self {
+        WebSocketConfig {
+            max_message_size: 16 * 1024,
+            fragment_size: 1024,
+            ping_interval: Some(Duration::from_secs(30)),
+            pong_timeout: Duration::from_secs(10),
+            handshake_timeout: Some(Duration::from_secs(10)),
+            close_timeout: Duration::from_secs(5),
+            protocols: vec![],
+            headers: vec![],
+            random
+        }
+    }
+
+    pub fn with_protocol(mut self, protocol: &str) -> Self {
+        self.protocols.push(protocol.to_string());
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String
+}
+
+/// A complete message received from the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// The server closed the connection, the close was already confirmed.
+    Close(Option<CloseFrame>)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    Open,
+    /// Our close frame was sent, waiting for the server's.
+    CloseSent,
+    Closed
+}
+
+/// A client connection, RFC 6455.
+///
+/// Pings from the server are answered while receiving. Both `recv` and the
+/// sending methods can be cancelled, for example to send while waiting for
+/// a message with `select`, as the unsent frames are kept and completed by
+/// the next call.
+pub struct WebSocket<T, E> {
+    logger: Logger,
+    socket: T,
+    env: E,
+    config: WebSocketConfig,
+    protocol: Option<String>,
+    state: State,
+    /// Received data that isn't a complete frame yet.
+    input: Vec<u8>,
+    /// Frames not completely sent.
+    output: Vec<u8>,
+    /// The opcode and the data of a fragmented message being received.
+    fragments: Option<(Opcode, Vec<u8>)>,
+    ping_sent: bool
+}
+
+impl<T, E> WebSocket<T, E>
+    where T: TcpSocket, E: SystemEnvironment
+{
+    /// Opens a `ws://` connection through the stack.
+    pub async fn connect<S>(logger: &Logger, stack: &mut S, env: E, url: &str, config: WebSocketConfig) -> Result<Self, WebSocketError>
+        where S: TcpStack<TcpSocket = T>
+    {
+        let parsed = Url::parse(url).map_err(|_| WebSocketError::UrlParseError)?.1;
+        if parsed.scheme != "ws" {
+            return Err(WebSocketError::UnsupportedUrlScheme(parsed.scheme));
+        }
+        let port = parsed.port_or_default().ok_or(WebSocketError::UrlPortParseError)?;
+
+        let connect = async {
+            let addr = resolve(stack, &parsed, port).await?;
+            stack.create_socket_connected(addr).await
+        };
+        let socket = match config.handshake_timeout {
+            Some(timeout) => with_timeout(&env, connect, timeout).await.map_err(|_| WebSocketError::Timeout)??,
+            None => connect.await?
+        };
+
+        Self::handshake(logger, socket, env, url, config).await
+    }
+
+    /// Upgrades an open connection. For `wss://`, this is the socket of a
+    /// TLS layer that was connected to the server.
+    pub async fn handshake(logger: &Logger, socket: T, env: E, url: &str, config: WebSocketConfig) -> Result<Self, WebSocketError> {
+        let url = Url::parse(url).map_err(|_| WebSocketError::UrlParseError)?.1;
+        if url.scheme != "ws" && url.scheme != "wss" {
+            return Err(WebSocketError::UnsupportedUrlScheme(url.scheme));
+        }
+
+        let mut ws = WebSocket {
+            logger: logger.new(o!("ctx" => "websocket_client")),
+            socket,
+            env,
+            config,
+            protocol: None,
+            state: State::Open,
+            input: vec![],
+            output: vec![],
+            fragments: None,
+            ping_sent: false
+        };
+
+        let env = ws.env.clone();
+        match ws.config.handshake_timeout {
+            Some(timeout) => with_timeout(&env, ws.upgrade(&url), timeout).await.map_err(|_| WebSocketError::Timeout)??,
+            None => ws.upgrade(&url).await?
+        }
+
+        info!(ws.logger, "Connected to {}, subprotocol {:?}", url.host(), ws.protocol);
+        Ok(ws)
+    }
+
+    /// The subprotocol chosen by the server.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state == State::Open
+    }
+
+    pub async fn send_text(&mut self, text: &str) -> Result<(), WebSocketError> {
+        self.send_message(Opcode::Text, text.as_bytes()).await
+    }
+
+    pub async fn send_binary(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.send_message(Opcode::Binary, data).await
+    }
+
+    /// Sends a ping, its pong is consumed by `recv`.
+    pub async fn ping(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+        if data.len() > 125 {
+            return Err(WebSocketError::ControlFrameTooLarge);
+        }
+        self.check_open()?;
+        self.queue_frame(true, Opcode::Ping, data);
+        self.flush().await
+    }
+
+    /// Waits for the next message. Returns `Message::Close` once when the
+    /// server closes the connection, `WebSocketError::Closed` after that.
+    pub async fn recv(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            if self.state == State::Closed {
+                let _ = self.flush().await;
+                self.output.clear();
+                return Err(WebSocketError::Closed);
+            }
+            match self.next_message() {
+                Ok(Some(message)) => {
+                    // the pongs, or the confirmation of a close from the server
+                    let _ = self.flush().await;
+                    return Ok(message);
+                },
+                // pongs and keepalive pings go out before waiting for more data
+                Ok(None) => self.flush().await?,
+                Err(e) => {
+                    warn!(self.logger, "Closing the connection: {:?}", e);
+                    let _ = self.flush().await;
+                    self.output.clear();
+                    return Err(e);
+                }
+            }
+
+            self.fill().await?;
+        }
+    }
+
+    /// Starts the close handshake and waits for the server to confirm it.
+    /// Messages that arrive in the meantime are dropped.
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if self.state == State::Open {
+            let mut payload = Vec::from(code.to_be_bytes());
+            let mut end = reason.len().min(123);
+            while !reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&reason.as_bytes()[..end]);
+
+            self.queue_frame(true, Opcode::Close, &payload);
+            self.state = State::CloseSent;
+        }
+        self.flush().await?;
+
+        let env = self.env.clone();
+        let timeout = self.config.close_timeout;
+        let confirmed = with_timeout(&env, async {
+            loop {
+                match self.recv().await {
+                    Ok(Message::Close(_)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        }, timeout).await;
+        if confirmed.is_err() {
+            info!(self.logger, "The server didn't confirm the close.");
+        }
+
+        self.state = State::Closed;
+        self.output.clear();
+        Ok(())
+    }
+
+    async fn upgrade(&mut self, url: &Url) -> Result<(), WebSocketError> {
+        let mut random = [0; 16];
+        for chunk in random.chunks_mut(4) {
+            chunk.copy_from_slice(&(self.config.random)().to_be_bytes());
+        }
+        let key = generate_key(random);
+        let head = request_head(url, &key, &self.config);
+        debug!(self.logger, "Upgrade request: {}", head);
+        self.output.extend_from_slice(head.as_bytes());
+        self.flush().await?;
+
+        let mut buf = [0; 512];
+        loop {
+            if let Some((size, protocol)) = check_response(&self.input, &key, &self.config)? {
+                // frames sent right after the response stay buffered
+                self.input.drain(..size);
+                self.protocol = protocol;
+                return Ok(());
+            }
+            if self.input.len() > MAX_HANDSHAKE_SIZE {
+                return Err(WebSocketError::InvalidHandshake);
+            }
+
+            let n = self.socket.read(&mut buf).await?;
+            if n == 0 {
+                return Err(TcpError::Closed.into());
+            }
+            self.input.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    fn check_open(&self) -> Result<(), WebSocketError> {
+        match self.state {
+            State::Open => Ok(()),
+            _ => Err(WebSocketError::Closed)
+        }
+    }
+
+    async fn send_message(&mut self, opcode: Opcode, data: &[u8]) -> Result<(), WebSocketError> {
+        self.check_open()?;
+
+        let mut chunks = data.chunks(self.config.fragment_size.max(1)).peekable();
+        if chunks.peek().is_none() {
+            self.queue_frame(true, opcode, &[]);
+        }
+        let mut opcode = opcode;
+        while let Some(chunk) = chunks.next() {
+            self.queue_frame(chunks.peek().is_none(), opcode, chunk);
+            opcode = Opcode::Continuation;
+        }
+
+        self.flush().await
+    }
+
+    fn queue_frame(&mut self, fin: bool, opcode: Opcode, payload: &[u8]) {
+        let mask = (self.config.random)().to_be_bytes();
+        FrameHeader { fin, opcode, mask: Some(mask), len: payload.len() as u64 }.encode(&mut self.output);
+
+        let start = self.output.len();
+        self.output.extend_from_slice(payload);
+        apply_mask(&mut self.output[start..], mask);
+    }
+
+    /// Sends the queued frames, keeping what wasn't sent if cancelled.
+    async fn flush(&mut self) -> Result<(), WebSocketError> {
+        while !self.output.is_empty() {
+            let n = self.socket.send(&self.output).await?;
+            if n == 0 {
+                self.state = State::Closed;
+                return Err(TcpError::Closed.into());
+            }
+            self.output.drain(..n);
+        }
+        Ok(())
+    }
+
+    /// Reads from the socket, sending keepalive pings while it is quiet.
+    async fn fill(&mut self) -> Result<(), WebSocketError> {
+        let mut buf = [0; 512];
+
+        let read = match self.config.ping_interval {
+            Some(interval) if self.state == State::Open => {
+                let wait = if self.ping_sent { self.config.pong_timeout } else { interval };
+                with_timeout(&self.env, self.socket.read(&mut buf), wait).await.ok()
+            },
+            _ => Some(self.socket.read(&mut buf).await)
+        };
+
+        let n = match read {
+            Some(r) => r?,
+            None if self.ping_sent => {
+                warn!(self.logger, "No answer to the keepalive ping.");
+                self.state = State::Closed;
+                return Err(WebSocketError::KeepaliveTimeout);
+            },
+            None => {
+                debug!(self.logger, "Sending a keepalive ping.");
+                self.queue_frame(true, Opcode::Ping, &[]);
+                self.ping_sent = true;
+                return Ok(());
+            }
+        };
+
+        if n == 0 {
+            self.state = State::Closed;
+            return Err(TcpError::Closed.into());
+        }
+        // anything received shows the connection is alive
+        self.ping_sent = false;
+        self.input.extend_from_slice(&buf[..n]);
+        Ok(())
+    }
+
+    /// Handles the buffered frames until a message is complete.
+    fn next_message(&mut self) -> Result<Option<Message>, WebSocketError> {
+        loop {
+            let (header, size) = match FrameHeader::parse(&self.input) {
+                Ok(Some(h)) => h,
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(self.fail(1002, e))
+            };
+
+            let buffered = self.fragments.as_ref().map(|f| f.1.len()).unwrap_or(0);
+            if header.len > (self.config.max_message_size - buffered.min(self.config.max_message_size)) as u64 {
+                return Err(self.fail(1009, WebSocketError::MessageTooLarge));
+            }
+            if header.mask.is_some() {
+                return Err(self.fail(1002, WebSocketError::ProtocolError));
+            }
+            let end = size + header.len as usize;
+            if self.input.len() < end {
+                return Ok(None);
+            }
+
+            let payload: Vec<u8> = self.input.drain(..end).skip(size).collect();
+
+            match header.opcode {
+                Opcode::Ping => {
+                    if self.state == State::Open {
+                        self.queue_frame(true, Opcode::Pong, &payload);
+                    }
+                },
+                Opcode::Pong => {},
+                Opcode::Close => return self.received_close(&payload).map(Some),
+                Opcode::Text | Opcode::Binary if self.fragments.is_none() => {
+                    if header.fin {
+                        return self.complete(header.opcode, payload).map(Some);
+                    }
+                    self.fragments = Some((header.opcode, payload));
+                },
+                Opcode::Continuation if self.fragments.is_some() => {
+                    if let Some((_, ref mut data)) = self.fragments {
+                        data.extend_from_slice(&payload);
+                    }
+                    if header.fin {
+                        let (opcode, data) = self.fragments.take().unwrap_or((Opcode::Binary, vec![]));
+                        return self.complete(opcode, data).map(Some);
+                    }
+                },
+                // a new message in the middle of a fragmented one, or a continuation of nothing
+                _ => return Err(self.fail(1002, WebSocketError::ProtocolError))
+            }
+        }
+    }
+
+    fn complete(&mut self, opcode: Opcode, data: Vec<u8>) -> Result<Message, WebSocketError> {
+        match opcode {
+            Opcode::Text => String::from_utf8(data)
+                .map(Message::Text)
+                .map_err(|_| self.fail(1007, WebSocketError::InvalidUtf8)),
+            _ => Ok(Message::Binary(data))
+        }
+    }
+
+    fn received_close(&mut self, payload: &[u8]) -> Result<Message, WebSocketError> {
+        let frame = match payload {
+            [] => None,
+            [_] => return Err(self.fail(1002, WebSocketError::ProtocolError)),
+            [a, b, reason @ ..] => {
+                let reason = core::str::from_utf8(reason).map_err(|_| self.fail(1007, WebSocketError::InvalidUtf8))?;
+                Some(CloseFrame { code: u16::from_be_bytes([*a, *b]), reason: reason.to_string() })
+            }
+        };
+        info!(self.logger, "Closed by the server: {:?}", frame);
+
+        if self.state == State::Open {
+            let code = frame.as_ref().map(|f| Vec::from(f.code.to_be_bytes())).unwrap_or_default();
+            self.queue_frame(true, Opcode::Close, &code);
+        }
+        self.state = State::Closed;
+        Ok(Message::Close(frame))
+    }
+
+    /// Queues a close with the status code, the connection can't be used anymore.
+    fn fail(&mut self, code: u16, e: WebSocketError) -> WebSocketError {
+        if self.state == State::Open {
+            self.queue_frame(true, Opcode::Close, &code.to_be_bytes());
+        }
+        self.state = State::Closed;
+        e
+    }
+}
+
+async fn resolve<S>(stack: &mut S, url: &Url, port: u16) -> Result<SocketAddr, TcpError>
+    where S: TcpStack
+{
+    match url.authority {
+        Authority::Hostname(ref h) => stack.get_socket_address(&format!("{}:{}", h, port)).await,
+        Authority::Ip((a, b, c, d)) => Ok(SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), port).into())
+    }
+}
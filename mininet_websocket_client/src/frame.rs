@@ -0,0 +1,172 @@
+use alloc::vec::Vec;
+
+use crate::WebSocketError;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong
+}
+
+impl Opcode {
+    pub fn from_u8(v: u8) -> Option<Opcode> {
+        match v {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA
+        }
+    }
+
+    /// Close, ping and pong, which can't be fragmented.
+    pub fn is_control(self) -> bool {
+        self.to_u8() & 0x8 != 0
+    }
+}
+
+/// The header of a frame, RFC 6455 section 5.2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The last frame of a message.
+    pub fin: bool,
+    pub opcode: Opcode,
+    /// Frames sent by a client are masked, frames from a server are not.
+    pub mask: Option<[u8; 4]>,
+    pub len: u64
+}
+
+impl FrameHeader {
+    /// Parses a header from the start of the data, with its size.
+    /// `Ok(None)` if more data is needed.
+    pub fn parse(data: &[u8]) -> Result<Option<(FrameHeader, usize)>, WebSocketError> {
+        if data.len() < 2 {
+            return Ok(None);
+        }
+
+        // no extension is negotiated, so the reserved bits must be clear
+        if data[0] & 0x70 != 0 {
+            return Err(WebSocketError::ProtocolError);
+        }
+        let fin = data[0] & 0x80 != 0;
+        let opcode = Opcode::from_u8(data[0] & 0x0F).ok_or(WebSocketError::ProtocolError)?;
+        let masked = data[1] & 0x80 != 0;
+
+        let (len, mut size) = match data[1] & 0x7F {
+            126 => match data.get(2..4) {
+                Some(b) => (u16::from_be_bytes([b[0], b[1]]) as u64, 4),
+                None => return Ok(None)
+            },
+            127 => match data.get(2..10) {
+                Some(b) => {
+                    let mut len = [0; 8];
+                    len.copy_from_slice(b);
+                    (u64::from_be_bytes(len), 10)
+                },
+                None => return Ok(None)
+            },
+            len => (len as u64, 2)
+        };
+        if len >> 63 != 0 || (opcode.is_control() && (!fin || len > 125)) {
+            return Err(WebSocketError::ProtocolError);
+        }
+
+        let mask = match masked {
+            true => match data.get(size..size + 4) {
+                Some(b) => {
+                    size += 4;
+                    Some([b[0], b[1], b[2], b[3]])
+                },
+                None => return Ok(None)
+            },
+            false => None
+        };
+
+        Ok(Some((FrameHeader { fin, opcode, mask, len }, size)))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(((self.fin as u8) << 7) | self.opcode.to_u8());
+
+        let mask_bit = (self.mask.is_some() as u8) << 7;
+        match self.len {
+            len @ 0..=125 => out.push(mask_bit | len as u8),
+            len @ 126..=0xFFFF => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            },
+            len => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&len.to_be_bytes());
+            }
+        }
+
+        if let Some(mask) = self.mask {
+            out.extend_from_slice(&mask);
+        }
+    }
+}
+
+/// Masks or unmasks the payload in place.
+pub fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn frame_examples() {
+        // RFC 6455 section 5.7
+        let (header, size) = FrameHeader::parse(&[0x81, 0x05, 0x48, 0x65]).unwrap().unwrap();
+        assert_eq!((FrameHeader { fin: true, opcode: Opcode::Text, mask: None, len: 5 }, 2), (header, size));
+
+        let masked = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (header, size) = FrameHeader::parse(&masked).unwrap().unwrap();
+        let mut payload = masked[size..].to_vec();
+        apply_mask(&mut payload, header.mask.unwrap());
+        assert_eq!(b"Hello", payload.as_slice());
+
+        let mut out = vec![];
+        header.encode(&mut out);
+        assert_eq!(&masked[..6], out.as_slice());
+
+        let (header, size) = FrameHeader::parse(&[0x01, 0x03, 0x48, 0x65, 0x6c]).unwrap().unwrap();
+        assert_eq!((false, Opcode::Text, 2), (header.fin, header.opcode, size));
+
+        let mut out = vec![];
+        FrameHeader { fin: true, opcode: Opcode::Binary, mask: None, len: 65536 }.encode(&mut out);
+        assert_eq!(vec![0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0], out);
+        assert_eq!(Some(65536), FrameHeader::parse(&out).unwrap().map(|h| h.0.len));
+        assert_eq!(None, FrameHeader::parse(&out[..9]).unwrap());
+    }
+
+    #[test]
+    fn frame_errors() {
+        // reserved bits, an unknown opcode, a fragmented ping and a long close
+        for data in [&[0xC1, 0x00][..], &[0x83, 0x00], &[0x09, 0x00], &[0x88, 0x7E, 0x00, 0x80]] {
+            assert!(matches!(FrameHeader::parse(data), Err(WebSocketError::ProtocolError)), "{:?}", data);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::{format, string::{String, ToString}};
+use mininet_base::url::Url;
+use sha1::{Digest, Sha1};
+
+use crate::{WebSocketError, client::WebSocketConfig};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The `Sec-WebSocket-Accept` a server answers to the key.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    base64_encode(&sha1.finalize())
+}
+
+pub(crate) fn request_head(url: &Url, key: &str, config: &WebSocketConfig) -> String {
+    let host = match url.port {
+        Some(port) if Some(port) != url.default_port() => format!("{}:{}", url.host(), port),
+        _ => url.host()
+    };
+
+    let mut head = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+        url.path_or_root(), host, key
+    );
+    if !config.protocols.is_empty() {
+        head.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", config.protocols.join(", ")));
+    }
+    for (name, value) in &config.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+/// Checks the upgrade response. Returns the size of the head and the
+/// subprotocol chosen by the server, `None` if the head is incomplete.
+pub(crate) fn check_response(data: &[u8], key: &str, config: &WebSocketConfig) -> Result<Option<(usize, Option<String>)>, WebSocketError> {
+    let mut headers = [httparse::EMPTY_HEADER; 24];
+    let mut response = httparse::Response::new(&mut headers);
+    let size = match response.parse(data)? {
+        httparse::Status::Complete(size) => size,
+        httparse::Status::Partial => return Ok(None)
+    };
+
+    let code = response.code.unwrap_or(0);
+    if code != 101 {
+        return Err(WebSocketError::HandshakeFailed(code));
+    }
+
+    let header = |name: &str| response.headers.iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .and_then(|h| core::str::from_utf8(h.value).ok())
+        .map(|v| v.trim());
+
+    let upgrade = header("Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let connection = header("Connection").is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("upgrade")));
+    let accepted = header("Sec-WebSocket-Accept") == Some(accept_key(key).as_str());
+    // no extension was offered
+    if !upgrade || !connection || !accepted || header("Sec-WebSocket-Extensions").is_some() {
+        return Err(WebSocketError::InvalidHandshake);
+    }
+
+    let protocol = header("Sec-WebSocket-Protocol").map(|p| p.to_string());
+    if protocol.as_ref().is_some_and(|p| !config.protocols.contains(p)) {
+        return Err(WebSocketError::InvalidHandshake);
+    }
+
+    Ok(Some((size, protocol)))
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut r = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                r.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                r.push('=');
+            }
+        }
+    }
+    r
+}
+
+pub(crate) fn generate_key(random: [u8; 16]) -> String {
+    base64_encode(&random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_accept() {
+        // RFC 6455 section 1.3
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key(key));
+        assert_eq!(key, generate_key(*b"the sample nonce"));
+
+        let config = WebSocketConfig::new(|| 4).with_protocol("chat");
+        let url = Url::parse("ws://server.example.com:80/chat").unwrap().1;
+        let head = request_head(&url, key, &config);
+        assert!(head.starts_with("GET /chat HTTP/1.1\r\nHost: server.example.com\r\n"), "{}", head);
+        assert!(head.ends_with("Sec-WebSocket-Protocol: chat\r\n\r\n"));
+
+        let response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\nSec-WebSocket-Protocol: chat\r\n\r\n\x81";
+        let (size, protocol) = check_response(response, key, &config).unwrap().unwrap();
+        assert_eq!((response.len() - 1, Some("chat")), (size, protocol.as_deref()));
+        assert!(check_response(&response[..40], key, &config).unwrap().is_none());
+
+        let wrong = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: x3JJHMbDL1EzLkh9GBhXDw==\r\n\r\n";
+        assert!(matches!(check_response(wrong, key, &config), Err(WebSocketError::InvalidHandshake)));
+        assert!(matches!(check_response(b"HTTP/1.1 403 Forbidden\r\n\r\n", key, &config), Err(WebSocketError::HandshakeFailed(403))));
+    }
+}
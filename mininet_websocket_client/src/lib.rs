@@ -0,0 +1,55 @@
+#![no_std]
+
+extern crate alloc;
+
+pub mod client;
+pub mod frame;
+pub mod handshake;
+
+use alloc::string::String;
+use mininet_base::stack::TcpError;
+
+pub use client::{CloseFrame, Message, WebSocket, WebSocketConfig};
+pub use frame::{FrameHeader, Opcode};
+
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    TcpError(TcpError),
+    HttpParseError(httparse::Error),
+    UrlParseError,
+    UrlPortParseError,
+    /// Only `ws` is connected by the client itself, `wss` needs a TLS socket
+    /// passed to `WebSocket::handshake`.
+    UnsupportedUrlScheme(String),
+    /// The server answered the upgrade with this status code.
+    HandshakeFailed(u16),
+    /// The upgrade response was missing a header or had a wrong `Sec-WebSocket-Accept`.
+    InvalidHandshake,
+    /// The server broke the framing rules, the connection was closed with 1002.
+    ProtocolError,
+    /// A text message or a close reason wasn't UTF-8, the connection was closed with 1007.
+    InvalidUtf8,
+    /// A message exceeded `max_message_size`, the connection was closed with 1009.
+    MessageTooLarge,
+    /// A control frame can carry at most 125 bytes.
+    ControlFrameTooLarge,
+    /// The server didn't answer a keepalive ping.
+    KeepaliveTimeout,
+    /// The connection or the whole handshake took too long.
+    Timeout,
+    /// The close handshake is done, or the connection was lost.
+    Closed
+}
+
+impl From<TcpError> for WebSocketError {
+    fn from(e: TcpError) -> Self {
+        Self::TcpError(e)
+    }
+}
+
+impl From<httparse::Error> for WebSocketError {
+    fn from(e: httparse::Error) -> Self {
+        Self::HttpParseError(e)
+    }
+}